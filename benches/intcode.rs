@@ -0,0 +1,78 @@
+// Times the Intcode interpreter on a few representative workloads. Run with
+// `cargo bench --bench intcode`.
+use std::time::{Duration, Instant};
+
+use aoc2019::intcode::{assemble, Intcode};
+
+// A tight loop summing the numbers below the input, which exercises instruction
+// dispatch and operand loads in every parameter mode.
+const SUM_LOOP: &str = "
+            IN -> [limit]
+    loop:   ADD [total], [rb+counter] -> [total]
+            ADD [rb+counter], #1 -> [rb+counter]
+            LT [rb+counter], [limit] -> [flag]
+            JNZ [flag], #loop
+            OUT [total]
+            HALT
+    limit:   .data 0
+    total:   .data 0
+    counter: .data 0
+    flag:    .data 0
+";
+
+// A short straight-line program in the style of day 2, which is cloned and run many
+// times with the parameters of its first instruction patched each time.
+const PATCHED: &str = "
+            ADD #0, #0 -> [result]
+            MUL [result], #3 -> [result]
+            ADD [result], [rb+1] -> [result]
+            MUL [result], [result] -> [0]
+            HALT
+    result: .data 0
+";
+
+fn time<F: FnMut() -> i64>(name: &str, iterations: u32, mut f: F) {
+    let mut best = Duration::from_secs(u64::MAX);
+    let mut checksum = 0;
+    for _ in 0..iterations {
+        let start = Instant::now();
+        checksum = f();
+        best = best.min(start.elapsed());
+    }
+    println!("{:<24} {:>10.2?} (checksum {})", name, best, checksum);
+}
+
+fn main() {
+    let sum_loop = Intcode::new(assemble(SUM_LOOP).unwrap());
+    time("sum loop (1M iterations)", 5, || {
+        let mut output = 0;
+        sum_loop
+            .clone()
+            .execute_with_io(|| 1_000_000, |v| output = v)
+            .unwrap();
+        output
+    });
+
+    let patched = Intcode::new(assemble(PATCHED).unwrap());
+    time("clone and patch (10k)", 20, || run_patched(&patched));
+
+    // The same, but with a large table after the code that every fork has to carry around
+    let mut image = patched.memory();
+    image.extend((1..=4000).map(|i| i * 7));
+    let large = Intcode::new(image);
+    time("clone 4k words (10k)", 20, || run_patched(&large));
+}
+
+fn run_patched(patched: &Intcode) -> i64 {
+    let mut checksum = 0;
+    for noun in 0..100 {
+        for verb in 0..100 {
+            let mut program = patched.clone();
+            *program.mem_access(1).unwrap() = noun;
+            *program.mem_access(2).unwrap() = verb;
+            program.execute().unwrap();
+            checksum += *program.mem_access(0).unwrap();
+        }
+    }
+    checksum
+}
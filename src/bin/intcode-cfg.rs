@@ -0,0 +1,19 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use aoc2019::intcode::control_flow_graph;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .ok_or("usage: intcode-cfg <program file>")?;
+    // The graph only covers the program as loaded, so there's no need for a VM
+    let memory = fs::read_to_string(path)?
+        .trim()
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<i64>, _>>()?;
+    print!("{}", control_flow_graph(&memory).to_dot(&memory));
+    Ok(())
+}
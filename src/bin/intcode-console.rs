@@ -0,0 +1,26 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io;
+
+use aoc2019::intcode::{Console, Intcode};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or("usage: intcode-console <program file> [transcript file]")?;
+    let transcript_path = args.next();
+    let program: Intcode = fs::read_to_string(path)?.trim().parse()?;
+    let mut console = Console::new(program);
+    if transcript_path.is_some() {
+        console = console.with_recording();
+    }
+    let stdin = io::stdin();
+    // Save the transcript even if the program crashed, since that's when it's most useful
+    let result = console.interact(stdin.lock(), io::stdout());
+    if let (Some(path), Some(transcript)) = (transcript_path, console.transcript()) {
+        transcript.save(File::create(path)?)?;
+    }
+    Ok(result?)
+}
@@ -0,0 +1,16 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io;
+
+use aoc2019::intcode::{Debugger, Intcode};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let path = env::args()
+        .nth(1)
+        .ok_or("usage: intcode-debugger <program file>")?;
+    let program: Intcode = fs::read_to_string(path)?.trim().parse()?;
+    let stdin = io::stdin();
+    Debugger::new(program).repl(stdin.lock(), io::stdout())?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::env;
+use std::error::Error;
+
+use aoc2019::intcode::FuzzCase;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let cases = match args.next() {
+        Some(cases) => cases.parse()?,
+        None => 10_000,
+    };
+    let first_seed = match args.next() {
+        Some(seed) => seed.parse()?,
+        None => 0,
+    };
+    for seed in first_seed..first_seed + cases {
+        let case = FuzzCase::generate(seed);
+        if let Err(failure) = case.check() {
+            let program = case.memory.iter().map(i64::to_string).collect::<Vec<_>>();
+            eprintln!("program: {}", program.join(","));
+            eprintln!("inputs: {:?}", case.inputs);
+            return Err(failure.into());
+        }
+    }
+    println!("{} cases passed", cases);
+    Ok(())
+}
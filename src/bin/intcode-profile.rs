@@ -0,0 +1,31 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use aoc2019::intcode::{Intcode, IoBus, Profile, Transcript};
+
+const HOT_SPOTS: usize = 20;
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or("usage: intcode-profile <program file> [transcript file]")?;
+    let mut program: Intcode = fs::read_to_string(path)?.trim().parse()?;
+
+    // The program reads the inputs recorded in the transcript, if there is one
+    let mut input = IoBus::default();
+    if let Some(path) = args.next() {
+        let transcript = Transcript::load(BufReader::new(File::open(path)?))?;
+        for value in transcript.inputs() {
+            input.write(value);
+        }
+    }
+
+    let mut profile = Profile::new();
+    let outcome = program.execute_traced(input, |_| {}, &mut profile)?;
+    println!("program stopped with {:?}", outcome);
+    print!("{}", profile.report(&program, HOT_SPOTS));
+    Ok(())
+}
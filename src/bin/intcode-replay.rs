@@ -0,0 +1,22 @@
+use std::env;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+
+use aoc2019::intcode::{Intcode, Transcript};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let usage = "usage: intcode-replay <program file> <transcript file>";
+    let program_path = args.next().ok_or(usage)?;
+    let transcript_path = args.next().ok_or(usage)?;
+    let mut program: Intcode = fs::read_to_string(program_path)?.trim().parse()?;
+    let transcript = Transcript::load(BufReader::new(File::open(transcript_path)?))?;
+    let outcome = transcript.replay(&mut program)?;
+    println!(
+        "replayed {} events, program stopped with {:?}",
+        transcript.events().len(),
+        outcome
+    );
+    Ok(())
+}
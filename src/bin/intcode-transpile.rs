@@ -0,0 +1,18 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+
+use aoc2019::intcode::{transpile, Intcode};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or("usage: intcode-transpile <program file> [intcode module path]")?;
+    let intcode_path = args
+        .next()
+        .unwrap_or_else(|| "aoc2019::intcode".to_string());
+    let program: Intcode = fs::read_to_string(path)?.trim().parse()?;
+    print!("{}", transpile(&program.memory(), &intcode_path));
+    Ok(())
+}
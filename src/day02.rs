@@ -1,6 +1,6 @@
 use std::num::ParseIntError;
 
-use crate::intcode::Intcode;
+use crate::intcode::{Intcode, SymbolicIntcode, VmError};
 
 #[aoc_generator(day2)]
 pub fn input_generator(input: &str) -> Result<Intcode, ParseIntError> {
@@ -8,26 +8,83 @@ pub fn input_generator(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day2, part1)]
-pub fn solve_part1(input: &Intcode) -> i64 {
+pub fn solve_part1(input: &Intcode) -> Result<i64, VmError> {
     let mut input = input.clone();
-    *input.mem_access(1) = 12;
-    *input.mem_access(2) = 2;
-    input.execute();
-    *input.mem_access(0)
+    *input.mem_access(1)? = 12;
+    *input.mem_access(2)? = 2;
+    input.execute()?;
+    Ok(*input.mem_access(0)?)
 }
 
+const TARGET: i64 = 19_690_720;
+
 #[aoc(day2, part2)]
-pub fn solve_part2(input: &Intcode) -> i64 {
+pub fn solve_part2(input: &Intcode) -> Result<i64, VmError> {
+    // The program only adds and multiplies, so the result is usually a linear function of
+    // the noun and verb that can be solved directly
+    let mut symbolic = SymbolicIntcode::new(input)
+        .with_symbol(1, "noun")
+        .with_symbol(2, "verb");
+    if symbolic.execute().is_ok() {
+        let ranges = [("noun", 0..=99), ("verb", 0..=99)];
+        let solution = symbolic
+            .read(0)
+            .and_then(|result| result.solve(TARGET, &ranges).ok().flatten());
+        if let Some(solution) = solution {
+            return Ok(100 * solution[0] + solution[1]);
+        }
+    }
+
     for noun in 0..=99 {
         for verb in 0..=99 {
             let mut test = input.clone();
-            *test.mem_access(1) = noun;
-            *test.mem_access(2) = verb;
-            test.execute();
-            if *test.mem_access(0) == 19_690_720 {
-                return 100 * noun + verb;
+            *test.mem_access(1)? = noun;
+            *test.mem_access(2)? = verb;
+            test.execute()?;
+            if *test.mem_access(0)? == TARGET {
+                return Ok(100 * noun + verb);
             }
         }
     }
     panic!("Couldn't find answer!");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "1,0,0,3,1,1,2,3,1,3,4,3,1,5,0,3,2,1,10,19,1,6,19,23,2,23,6,27,2,6,27,31,2,13,31,35,1,10,35,39,2,39,13,43,1,43,13,47,1,6,47,51,1,10,51,55,2,55,6,59,1,5,59,63,2,9,63,67,1,6,67,71,2,9,71,75,1,6,75,79,2,79,13,83,1,83,10,87,1,13,87,91,1,91,10,95,2,9,95,99,1,5,99,103,2,10,103,107,1,107,2,111,1,111,5,0,99,2,14,0,0";
+
+    #[test]
+    pub fn test_part2() {
+        let program = input_generator(PROGRAM).unwrap();
+        let answer = solve_part2(&program).unwrap();
+        assert_eq!(4559, answer);
+        let mut check = program.clone();
+        *check.mem_access(1).unwrap() = answer / 100;
+        *check.mem_access(2).unwrap() = answer % 100;
+        check.execute().unwrap();
+        assert_eq!(TARGET, *check.mem_access(0).unwrap());
+
+        let mut symbolic = SymbolicIntcode::new(&program)
+            .with_symbol(1, "noun")
+            .with_symbol(2, "verb");
+        symbolic.execute().unwrap();
+        assert_eq!(
+            "432000*noun + verb + 250661",
+            symbolic.read(0).unwrap().to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_part2_fallback() {
+        // Multiplies the noun by 2^64, which only fits for a noun of 0, then stores
+        // verb + TARGET - 7
+        let program = format!(
+            "1,0,0,20,1002,1,4611686018427387904,21,1002,21,4,21,1001,2,{},0,99,0,0,0,0,0",
+            TARGET - 7
+        );
+        let program = input_generator(&program).unwrap();
+        assert_eq!(7, solve_part2(&program).unwrap());
+    }
+}
@@ -1,6 +1,6 @@
 use std::num::ParseIntError;
 
-use crate::intcode::Intcode;
+use crate::intcode::{Intcode, VmError};
 
 #[aoc_generator(day5)]
 pub fn input_generator(input: &str) -> Result<Intcode, ParseIntError> {
@@ -8,17 +8,17 @@ pub fn input_generator(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day5, part1)]
-pub fn solve_part1(input: &Intcode) -> i64 {
+pub fn solve_part1(input: &Intcode) -> Result<i64, VmError> {
     let mut input = input.clone();
     let mut diagnostic_code = 0;
-    input.execute_with_io(|| 1, |v| diagnostic_code = v);
-    diagnostic_code
+    input.execute_with_io(|| 1, |v| diagnostic_code = v)?;
+    Ok(diagnostic_code)
 }
 
 #[aoc(day5, part2)]
-pub fn solve_part2(input: &Intcode) -> i64 {
+pub fn solve_part2(input: &Intcode) -> Result<i64, VmError> {
     let mut input = input.clone();
     let mut diagnostic_code = 0;
-    input.execute_with_io(|| 5, |v| diagnostic_code = v);
-    diagnostic_code
+    input.execute_with_io(|| 5, |v| diagnostic_code = v)?;
+    Ok(diagnostic_code)
 }
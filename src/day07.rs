@@ -2,7 +2,7 @@ use std::num::ParseIntError;
 
 use itertools::Itertools;
 
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Intcode, Network, VmError};
 
 #[aoc_generator(day7)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -10,56 +10,66 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day7, part1)]
-pub fn solve_part1(program: &Intcode) -> i64 {
-    (0..5)
+pub fn solve_part1(program: &Intcode) -> Result<i64, VmError> {
+    let outputs = (0..5)
         .permutations(5)
-        .map(|permutation| run_amplifier_circuit(program, &permutation, 0))
-        .max()
-        .unwrap()
-}
-
-fn run_amplifier_circuit(program: &Intcode, phase_settings: &[i64], input: i64) -> i64 {
-    let mut io: Vec<IoBus> = vec![Default::default(); phase_settings.len() + 1];
-    for i in 0..phase_settings.len() {
-        io[i].write(phase_settings[i]);
-    }
-    io[0].write(input);
-    for i in 0..phase_settings.len() {
-        let mut output = 0;
-        program
-            .clone()
-            .execute_with_io(&mut io[i], |val| output = val);
-        io[i + 1].write(output);
-    }
-    io[phase_settings.len()].read().unwrap()
+        .map(|permutation| run_amplifier_circuit(program, &permutation, 0, false))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(outputs.into_iter().max().unwrap())
 }
 
 #[aoc(day7, part2)]
-pub fn solve_part2(program: &Intcode) -> i64 {
-    (5..10)
+pub fn solve_part2(program: &Intcode) -> Result<i64, VmError> {
+    let outputs = (5..10)
         .permutations(5)
-        .map(|permutation| run_feedback_amplifier_circuit(program, &permutation, 0))
-        .max()
-        .unwrap()
+        .map(|permutation| run_amplifier_circuit(program, &permutation, 0, true))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(outputs.into_iter().max().unwrap())
 }
 
-fn run_feedback_amplifier_circuit(program: &Intcode, phase_settings: &[i64], input: i64) -> i64 {
-    let mut io: Vec<IoBus> = vec![Default::default(); phase_settings.len()];
-    let mut amplifiers = (0..phase_settings.len())
-        .map(|_| program.clone())
+// Runs a chain of amplifiers, or a loop of them when there's feedback, and returns the
+// last signal from the final amplifier
+fn run_amplifier_circuit(
+    program: &Intcode,
+    phase_settings: &[i64],
+    input: i64,
+    feedback: bool,
+) -> Result<i64, VmError> {
+    let mut network = Network::new();
+    let amplifiers = phase_settings
+        .iter()
+        .map(|&phase| {
+            let amplifier = network.add(program.clone());
+            network.send(amplifier, &[phase]);
+            amplifier
+        })
         .collect::<Vec<_>>();
-    for i in 0..phase_settings.len() {
-        io[i].write(phase_settings[i]);
+    if feedback {
+        network.connect_ring(&amplifiers);
+    } else {
+        network.connect_chain(&amplifiers);
     }
-    io[0].write(input);
-    while !amplifiers[phase_settings.len() - 1].is_halted() {
-        for i in 0..phase_settings.len() {
-            let mut temp = IoBus::default();
-            amplifiers[i].execute_with_io(&mut io[i], &mut temp);
-            while let Some(output) = temp.read() {
-                io[(i + 1) % phase_settings.len()].write(output);
-            }
-        }
+    network.send(amplifiers[0], &[input]);
+    network.run()?;
+    Ok(*network.outputs(*amplifiers.last().unwrap()).last().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    pub fn test_part1() {
+        let program = generate_input("3,15,3,16,1002,16,10,16,1,16,15,15,4,15,99,0,0").unwrap();
+        assert_eq!(Ok(43210), solve_part1(&program));
+    }
+
+    #[test]
+    pub fn test_part2() {
+        let program = generate_input(
+            "3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5",
+        )
+        .unwrap();
+        assert_eq!(Ok(139629729), solve_part2(&program));
     }
-    io[0].read().unwrap()
 }
@@ -1,6 +1,6 @@
 use std::num::ParseIntError;
 
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Intcode, IoBus, VmError};
 
 #[aoc_generator(day9)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -8,15 +8,15 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day9, part1)]
-pub fn solve_part1(program: &Intcode) -> i64 {
+pub fn solve_part1(program: &Intcode) -> Result<i64, VmError> {
     let mut output = IoBus::default();
-    program.clone().execute_with_io(|| 1, &mut output);
-    output.read().unwrap()
+    program.clone().execute_with_io(|| 1, &mut output)?;
+    Ok(output.read().unwrap())
 }
 
 #[aoc(day9, part2)]
-pub fn solve_part2(program: &Intcode) -> i64 {
+pub fn solve_part2(program: &Intcode) -> Result<i64, VmError> {
     let mut output = IoBus::default();
-    program.clone().execute_with_io(|| 2, &mut output);
-    output.read().unwrap()
+    program.clone().execute_with_io(|| 2, &mut output)?;
+    Ok(output.read().unwrap())
 }
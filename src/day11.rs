@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::num::ParseIntError;
 
 use crate::geometry::Point2D;
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Driver, Intcode, VmError};
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum Direction {
@@ -18,13 +18,13 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day11, part1)]
-pub fn solve_part1(program: &Intcode) -> usize {
-    paint_panels(program, 0).len()
+pub fn solve_part1(program: &Intcode) -> Result<usize, VmError> {
+    Ok(paint_panels(program, 0)?.len())
 }
 
 #[aoc(day11, part2)]
-pub fn solve_part2(program: &Intcode) -> String {
-    let panel_colors = paint_panels(program, 1);
+pub fn solve_part2(program: &Intcode) -> Result<String, VmError> {
+    let panel_colors = paint_panels(program, 1)?;
 
     let minx = panel_colors.keys().map(|k| k.x).min().unwrap();
     let maxx = panel_colors.keys().map(|k| k.x).max().unwrap();
@@ -43,13 +43,14 @@ pub fn solve_part2(program: &Intcode) -> String {
         }
         output.push('\n');
     }
-    output
+    Ok(output)
 }
 
-fn paint_panels(program: &Intcode, initial_panel: i64) -> HashMap<Point2D<i32>, i64> {
-    let mut robot = program.clone();
-    let mut input = IoBus::default();
-    let mut output = IoBus::default();
+fn paint_panels(
+    program: &Intcode,
+    initial_panel: i64,
+) -> Result<HashMap<Point2D<i32>, i64>, VmError> {
+    let mut robot = Driver::new(program.clone());
     let mut position = Point2D::origin();
     let mut direction = Direction::Up;
     let mut panel_colors = HashMap::new();
@@ -57,22 +58,24 @@ fn paint_panels(program: &Intcode, initial_panel: i64) -> HashMap<Point2D<i32>,
     // Set initial panel color
     panel_colors.insert(position, initial_panel);
 
-    while !robot.is_halted() {
+    loop {
         // Handle input
         let cur_color = *panel_colors.get(&position).unwrap_or(&0);
-        input.write(cur_color);
+        robot.send(&[cur_color]);
 
-        // Execute robot
-        robot.execute_with_io(&mut input, &mut output);
+        // Execute robot until it has painted and turned, or halted
+        let (new_color, rotation) = match robot.recv_n(2)?[..] {
+            [new_color, rotation] => (new_color, rotation),
+            _ => break,
+        };
 
         // Handle output
-        let (new_color, rotation) = (output.read().unwrap(), output.read().unwrap());
         panel_colors.insert(position, new_color);
         direction = direction.rotate(rotation);
         position = offset_point(position, direction);
     }
 
-    panel_colors
+    Ok(panel_colors)
 }
 
 fn offset_point(point: Point2D<i32>, direction: Direction) -> Point2D<i32> {
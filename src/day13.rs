@@ -2,7 +2,7 @@ use std::collections::HashMap;
 use std::num::ParseIntError;
 
 use crate::geometry::Point2D;
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Driver, Intcode, VmError};
 
 #[aoc_generator(day13)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -10,53 +10,43 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day13, part1)]
-pub fn solve_part1(program: &Intcode) -> usize {
-    let mut output = IoBus::default();
-    program.clone().execute_with_io(|| 0, &mut output);
-    let mut output_vec = Vec::new();
-    while let Some(v) = output.read() {
-        output_vec.push(v);
-    }
+pub fn solve_part1(program: &Intcode) -> Result<usize, VmError> {
+    let output_vec = Driver::new(program.clone())
+        .outputs()
+        .collect::<Result<Vec<_>, _>>()?;
 
     let mut tiles = HashMap::new();
     for tile in output_vec.chunks_exact(3) {
         tiles.insert(point2D!(tile[0], tile[1]), tile[2]);
     }
-    tiles.values().filter(|&&v| v == 2).count()
+    Ok(tiles.values().filter(|&&v| v == 2).count())
 }
 
 #[aoc(day13, part2)]
-pub fn solve_part2(program: &Intcode) -> i64 {
-    let mut game = program.clone();
-    let mut input = IoBus::default();
-    let mut output = IoBus::default();
+pub fn solve_part2(program: &Intcode) -> Result<i64, VmError> {
+    let mut game = Driver::new(program.clone());
 
     // Play for free!
-    *game.mem_access(0) = 2;
+    *game.program_mut().mem_access(0)? = 2;
 
     let mut tiles = HashMap::new();
     let mut score = 0;
-    while !game.is_halted() {
+    loop {
         // Compute input
-        input.write(compute_joystick(&tiles));
-
-        // Execute game step until next input request
-        game.execute_with_io(&mut input, &mut output);
+        game.send(&[compute_joystick(&tiles)]);
 
-        // Write all output to "screen"
-        let mut output_vec = Vec::new();
-        while let Some(v) = output.read() {
-            output_vec.push(v);
-        }
-        for chunk in output_vec.chunks_exact(3) {
-            if chunk[0] == -1 && chunk[1] == 0 {
-                score = chunk[2];
+        // Execute game step until next input request, writing all output to "screen"
+        while let [x, y, tile] = game.recv_n(3)?[..] {
+            if x == -1 && y == 0 {
+                score = tile;
             } else {
-                tiles.insert(point2D!(chunk[0], chunk[1]), chunk[2]);
+                tiles.insert(point2D!(x, y), tile);
             }
         }
+        if game.is_halted() {
+            return Ok(score);
+        }
     }
-    score
 }
 
 fn compute_joystick(tiles: &HashMap<Point2D<i64>, i64>) -> i64 {
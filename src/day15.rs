@@ -3,7 +3,7 @@ use std::fmt::{self, Display, Formatter};
 use std::num::ParseIntError;
 
 use crate::geometry::Point2D;
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Driver, Intcode, VmError};
 
 #[aoc_generator(day15)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -24,17 +24,17 @@ struct Map {
 }
 
 #[aoc(day15, part1)]
-pub fn solve_part1(program: &Intcode) -> usize {
-    let map = create_complete_map(program);
+pub fn solve_part1(program: &Intcode) -> Result<usize, VmError> {
+    let map = create_complete_map(program)?;
     let (_, distance) = map
         .find_tile_path(Point2D::origin(), |_, t| t == Tile::Oxygen)
         .expect("there should be a path to oxygen in a complete map");
-    distance
+    Ok(distance)
 }
 
 #[aoc(day15, part2)]
-pub fn solve_part2(program: &Intcode) -> usize {
-    let mut map = create_complete_map(program);
+pub fn solve_part2(program: &Intcode) -> Result<usize, VmError> {
+    let mut map = create_complete_map(program)?;
     let oxygen_source = *map
         .tiles
         .iter()
@@ -54,23 +54,25 @@ pub fn solve_part2(program: &Intcode) -> usize {
             }
         }
     }
-    max_minutes
+    Ok(max_minutes)
 }
 
-fn create_complete_map(program: &Intcode) -> Map {
-    let mut robot = program.clone();
-    let mut input = IoBus::default();
-    let mut output = IoBus::default();
+fn create_complete_map(program: &Intcode) -> Result<Map, VmError> {
+    explore(&mut Driver::new(program.clone()))
+}
+
+// Moves the robot around until every tile it can reach is known
+fn explore(robot: &mut Driver) -> Result<Map, VmError> {
     let mut map = Map::new();
     let mut position = Point2D::origin();
     map.tiles.insert(position, Tile::Open);
     while let Some((direction, _)) = map.find_tile_path(position, |_, t| t == Tile::Unknown) {
         let target_position = do_movement(position, direction);
 
-        input.write(direction);
-        robot.execute_with_io(&mut input, &mut output);
-
-        let status = output.read().unwrap();
+        robot.send(&[direction]);
+        let status = robot
+            .run_until_output()?
+            .expect("robot stopped without a status");
         if status == 0 {
             map.tiles.insert(target_position, Tile::Wall);
         } else if status == 1 {
@@ -83,7 +85,7 @@ fn create_complete_map(program: &Intcode) -> Map {
             panic!("uhh crap status is {}", status)
         }
     }
-    map
+    Ok(map)
 }
 
 fn do_movement(point: Point2D<i32>, direction: i64) -> Point2D<i32> {
@@ -165,3 +167,77 @@ impl Display for Map {
         writeln!(f)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, RunOutcome, Transcript};
+
+    // A droid in a 7x7 maze, starting at S with oxygen at O:
+    //
+    //     #######
+    //     #...#.#
+    //     #.#.#.#
+    //     #.#S..#
+    //     #.###O#
+    //     #.....#
+    //     #######
+    fn droid() -> Intcode {
+        let source = "
+            loop:   IN -> [dir]
+                    EQ [dir], #4 -> [step]
+                    ADD [x], [step] -> [nx]
+                    EQ [dir], #3 -> [step]
+                    MUL [step], #-1 -> [step]
+                    ADD [nx], [step] -> [nx]
+                    EQ [dir], #2 -> [step]
+                    ADD [y], [step] -> [ny]
+                    EQ [dir], #1 -> [step]
+                    MUL [step], #-1 -> [step]
+                    ADD [ny], [step] -> [ny]
+                    MUL [ny], #7 -> [cell]
+                    ADD [cell], [nx] -> [cell]
+                    ADD [cell], #maze -> [load+1]
+            load:   ADD [0], #0 -> [cell]
+                    OUT [cell]
+                    JZ [cell], #loop
+                    ADD [nx], #0 -> [x]
+                    ADD [ny], #0 -> [y]
+                    JZ #0, #loop
+            dir:    .data 0
+            step:   .data 0
+            x:      .data 3
+            y:      .data 3
+            nx:     .data 0
+            ny:     .data 0
+            cell:   .data 0
+            maze:   .data 0, 0, 0, 0, 0, 0, 0
+                    .data 0, 1, 1, 1, 0, 1, 0
+                    .data 0, 1, 0, 1, 0, 1, 0
+                    .data 0, 1, 0, 1, 1, 1, 0
+                    .data 0, 1, 0, 0, 0, 2, 0
+                    .data 0, 1, 1, 1, 1, 1, 0
+                    .data 0, 0, 0, 0, 0, 0, 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_solve() {
+        assert_eq!(Ok(3), solve_part1(&droid()));
+        assert_eq!(Ok(8), solve_part2(&droid()));
+    }
+
+    #[test]
+    pub fn test_golden_transcript() {
+        // Every move the explorer made and every status the droid reported, as recorded
+        // when the explorer was known to work
+        let golden =
+            Transcript::load(include_str!("testdata/day15.transcript").as_bytes()).unwrap();
+        assert_eq!(Ok(RunOutcome::NeedsInput), golden.replay(&mut droid()));
+
+        let mut robot = Driver::new(droid()).with_recording();
+        explore(&mut robot).unwrap();
+        assert_eq!(Some(&golden), robot.transcript());
+    }
+}
@@ -2,7 +2,7 @@ use std::iter;
 use std::num::ParseIntError;
 
 use crate::geometry::Point2D;
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Console, Intcode, VmError};
 
 #[aoc_generator(day17)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -10,8 +10,8 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day17, part1)]
-pub fn solve_part1(program: &Intcode) -> usize {
-    let mut display = print_display(program);
+pub fn solve_part1(program: &Intcode) -> Result<usize, VmError> {
+    let mut display = print_display(program)?;
     let mut alignment = 0;
     for y in 1..display.len() - 1 {
         for x in 1..display[y].len() - 1 {
@@ -32,24 +32,15 @@ pub fn solve_part1(program: &Intcode) -> usize {
         .flat_map(|line| line.iter().copied().chain(iter::once('\n')))
         .collect::<String>();
     println!("\n{}", s);
-    alignment
+    Ok(alignment)
 }
 
-fn print_display(program: &Intcode) -> Vec<Vec<char>> {
-    let mut program = program.clone();
-    let mut output = IoBus::default();
-    program.execute_with_io(|| 0, &mut output);
-
-    let mut screen = Vec::new();
-    screen.push(Vec::new());
-    let mut cur_line = screen.last_mut().unwrap();
-    while let Some(o) = output.read() {
-        if o == 10 {
-            screen.push(Vec::new());
-            cur_line = screen.last_mut().unwrap();
-        } else {
-            cur_line.push(o as u8 as char);
-        }
-    }
-    screen.into_iter().filter(|line| !line.is_empty()).collect()
+fn print_display(program: &Intcode) -> Result<Vec<Vec<char>>, VmError> {
+    let response = Console::new(program.clone()).run()?;
+    Ok(response
+        .lines
+        .iter()
+        .filter(|line| !line.is_empty())
+        .map(|line| line.chars().collect())
+        .collect())
 }
@@ -2,7 +2,7 @@ use std::collections::{hash_map::Entry, HashMap};
 use std::num::ParseIntError;
 
 use crate::geometry::Point2D;
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Intcode, IoBus, VmError};
 
 #[aoc_generator(day19)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -10,27 +10,27 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day19, part1)]
-pub fn solve_part1(program: &Intcode) -> usize {
+pub fn solve_part1(program: &Intcode) -> Result<usize, VmError> {
     let mut beam_tracker = BeamTracker::with_program(program);
     let mut pulled_points = 0;
     for y in 0..50 {
         for x in 0..50 {
-            if beam_tracker.is_pulled(x, y) {
+            if beam_tracker.is_pulled(x, y)? {
                 pulled_points += 1;
             }
         }
     }
-    pulled_points
+    Ok(pulled_points)
 }
 
 #[aoc(day19, part2)]
-pub fn solve_part2(program: &Intcode) -> i64 {
+pub fn solve_part2(program: &Intcode) -> Result<i64, VmError> {
     let mut beam_tracker = BeamTracker::with_program(program);
     let mut first_x = 0;
     for y in 0.. {
         // Find the last x that is pulled.
         let mut x = first_x;
-        while !beam_tracker.is_pulled(x, y) {
+        while !beam_tracker.is_pulled(x, y)? {
             x += 1;
         }
         first_x = x;
@@ -38,20 +38,20 @@ pub fn solve_part2(program: &Intcode) -> i64 {
         // Check if a spaceship would fit touching the bottom edge.
         if y >= 100
             && x >= 100
-            && beam_tracker.is_pulled(x, y - 99)
-            && beam_tracker.is_pulled(x + 99, y)
-            && beam_tracker.is_pulled(x + 99, y - 99)
+            && beam_tracker.is_pulled(x, y - 99)?
+            && beam_tracker.is_pulled(x + 99, y)?
+            && beam_tracker.is_pulled(x + 99, y - 99)?
         {
             let mut y = y - 99;
 
             // Make sure we can't move the ship up any (maybe not necessary?)
-            while y > 0 && beam_tracker.fits_spaceship_at(x, y - 1, 100) {
+            while y > 0 && beam_tracker.fits_spaceship_at(x, y - 1, 100)? {
                 y -= 1;
             }
-            return 10_000 * x + y;
+            return Ok(10_000 * x + y);
         }
     }
-    0
+    Ok(0)
 }
 
 struct BeamTracker {
@@ -67,30 +67,30 @@ impl BeamTracker {
         }
     }
 
-    pub fn is_pulled(&mut self, x: i64, y: i64) -> bool {
+    pub fn is_pulled(&mut self, x: i64, y: i64) -> Result<bool, VmError> {
         match self.point_statuses.entry(point2D!(x, y)) {
-            Entry::Occupied(o) => *o.get() == 1,
+            Entry::Occupied(o) => Ok(*o.get() == 1),
             Entry::Vacant(v) => {
                 let mut drone = self.program.clone();
                 let mut input = IoBus::default();
                 let mut output = IoBus::default();
                 input.write(x);
                 input.write(y);
-                drone.execute_with_io(&mut input, &mut output);
+                drone.execute_with_io(&mut input, &mut output)?;
                 let pulled = output.read().unwrap();
                 v.insert(pulled);
-                pulled == 1
+                Ok(pulled == 1)
             }
         }
     }
 
-    pub fn fits_spaceship_at(&mut self, x: i64, y: i64, size: i64) -> bool {
-        if !self.is_pulled(x + size - 1, y) {
-            return false;
+    pub fn fits_spaceship_at(&mut self, x: i64, y: i64, size: i64) -> Result<bool, VmError> {
+        if !self.is_pulled(x + size - 1, y)? {
+            return Ok(false);
         }
-        if !self.is_pulled(x, y + size - 1) {
-            return false;
+        if !self.is_pulled(x, y + size - 1)? {
+            return Ok(false);
         }
-        true
+        Ok(true)
     }
 }
@@ -1,6 +1,6 @@
 use std::num::ParseIntError;
 
-use crate::intcode::{Intcode, IoBus};
+use crate::intcode::{Console, Intcode, VmError};
 
 #[aoc_generator(day21)]
 pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
@@ -8,10 +8,8 @@ pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
 }
 
 #[aoc(day21, part1)]
-pub fn solve_part1(program: &Intcode) -> i64 {
-    let mut input = IoBus::default();
-    let mut output = IoBus::default();
-    let mut springdroid = program.clone();
+pub fn solve_part1(program: &Intcode) -> Result<i64, VmError> {
+    let mut springdroid = Console::new(program.clone());
 
     let program = "NOT B J
 NOT C T
@@ -20,18 +18,16 @@ NOT A T
 OR T J
 AND D J
 WALK\n";
-    input.write_str(program);
+    springdroid.send_text(program);
 
-    springdroid.execute_with_io(&mut input, &mut output);
-    println!("{}", output.read_str());
-    output.read().unwrap_or(-1)
+    let response = springdroid.run()?;
+    println!("{}", response.text());
+    Ok(response.values.first().copied().unwrap_or(-1))
 }
 
 #[aoc(day21, part2)]
-pub fn solve_part2(program: &Intcode) -> i64 {
-    let mut input = IoBus::default();
-    let mut output = IoBus::default();
-    let mut springdroid = program.clone();
+pub fn solve_part2(program: &Intcode) -> Result<i64, VmError> {
+    let mut springdroid = Console::new(program.clone());
 
     let program = "NOT A J
 NOT B T
@@ -44,9 +40,9 @@ NOT T T
 OR H T
 AND T J
 RUN\n";
-    input.write_str(program);
+    springdroid.send_text(program);
 
-    springdroid.execute_with_io(&mut input, &mut output);
-    println!("{}", output.read_str());
-    output.read().unwrap_or(-1)
+    let response = springdroid.run()?;
+    println!("{}", response.text());
+    Ok(response.values.first().copied().unwrap_or(-1))
 }
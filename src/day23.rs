@@ -0,0 +1,243 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::num::ParseIntError;
+
+use crate::intcode::{Input, Intcode, IoBus, VmError};
+
+const COMPUTERS: usize = 50;
+const NAT_ADDRESS: i64 = 255;
+
+#[aoc_generator(day23)]
+pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
+    input.parse()
+}
+
+#[aoc(day23, part1)]
+pub fn solve_part1(program: &Intcode) -> Result<i64, NetworkError> {
+    let mut network = PacketNetwork::boot(program);
+    loop {
+        let active = network.run_round()?;
+        if let Some((_, y)) = network.nat {
+            return Ok(y);
+        }
+        if !active {
+            return Err(NetworkError::Idle);
+        }
+    }
+}
+
+#[aoc(day23, part2)]
+pub fn solve_part2(program: &Intcode) -> Result<i64, NetworkError> {
+    let mut network = PacketNetwork::boot(program);
+    let mut last_delivered = None;
+    loop {
+        if network.run_round()? {
+            continue;
+        }
+
+        // The network is idle, so the NAT wakes it up again
+        let (x, y) = network.nat.ok_or(NetworkError::Idle)?;
+        if last_delivered == Some(y) {
+            return Ok(y);
+        }
+        last_delivered = Some(y);
+        network.computers[0].queue.write(x);
+        network.computers[0].queue.write(y);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum NetworkError {
+    Vm(VmError),
+    // Every computer is waiting for packets, and none has been sent to the NAT
+    Idle,
+}
+
+// Unlike `intcode::Network`, which delivers each output to machines chosen when they're
+// connected and leaves machines waiting for input alone, packets here go wherever their
+// first word says, and computers keep running on -1 while there's nothing for them
+struct PacketNetwork {
+    computers: Vec<Computer>,
+    // The last packet sent to the NAT
+    nat: Option<(i64, i64)>,
+}
+
+struct Computer {
+    program: Intcode,
+    queue: IoBus,
+    // Output that doesn't make up a whole packet yet
+    output: IoBus,
+}
+
+// Feeds a computer its incoming packets, or -1 if there aren't any. Reading from an empty
+// queue a second time stops the computer until the next round, since by then it's just
+// polling for packets that haven't been sent yet.
+struct Receiver<'a> {
+    queue: &'a mut IoBus,
+    polled: bool,
+}
+
+impl PacketNetwork {
+    fn boot(program: &Intcode) -> Self {
+        let computers = (0..COMPUTERS)
+            .map(|address| {
+                let mut queue = IoBus::default();
+                queue.write(address as i64);
+                Computer {
+                    program: program.clone(),
+                    queue,
+                    output: IoBus::default(),
+                }
+            })
+            .collect();
+        Self {
+            computers,
+            nat: None,
+        }
+    }
+
+    // Runs every computer until it's waiting for packets, returning whether any packets
+    // were received or sent during the round
+    fn run_round(&mut self) -> Result<bool, VmError> {
+        let mut active = false;
+        for i in 0..self.computers.len() {
+            let computer = &mut self.computers[i];
+            active |= !computer.queue.is_empty();
+            let receiver = Receiver {
+                queue: &mut computer.queue,
+                polled: false,
+            };
+            computer
+                .program
+                .execute_with_io(receiver, &mut computer.output)?;
+
+            let mut packets = Vec::new();
+            while computer.output.len() >= 3 {
+                let mut read = || computer.output.read().unwrap();
+                packets.push((read(), read(), read()));
+            }
+            for (destination, x, y) in packets {
+                active = true;
+                // Packets to addresses nobody has are lost, like on a real network
+                if destination == NAT_ADDRESS {
+                    self.nat = Some((x, y));
+                } else if let Some(computer) = usize::try_from(destination)
+                    .ok()
+                    .and_then(|destination| self.computers.get_mut(destination))
+                {
+                    computer.queue.write(x);
+                    computer.queue.write(y);
+                }
+            }
+        }
+        Ok(active)
+    }
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            NetworkError::Vm(e) => write!(f, "{}", e),
+            NetworkError::Idle => write!(f, "the network went idle before the NAT got a packet"),
+        }
+    }
+}
+
+impl Error for NetworkError {}
+
+impl From<VmError> for NetworkError {
+    fn from(e: VmError) -> Self {
+        NetworkError::Vm(e)
+    }
+}
+
+impl Input for Receiver<'_> {
+    fn get_input(&mut self) -> Option<i64> {
+        match self.queue.read() {
+            Some(value) => Some(value),
+            None if !self.polled => {
+                self.polled = true;
+                Some(-1)
+            }
+            None => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Computer 0 sends the NAT a packet when it boots, and every computer answers packets
+    // by sending the NAT the same packet with y increased by one, up to 3
+    fn program() -> Intcode {
+        let source = "
+                    IN -> [address]
+                    JNZ [address], #loop
+                    OUT #255
+                    OUT #0
+                    OUT #1
+            loop:   IN -> [x]
+                    EQ [x], #-1 -> [flag]
+                    JNZ [flag], #loop
+                    IN -> [y]
+                    LT [y], #3 -> [flag]
+                    ADD [y], [flag] -> [y]
+                    OUT #255
+                    OUT [x]
+                    OUT [y]
+                    JZ #0, #loop
+            address: .data 0
+            x:       .data 0
+            y:       .data 0
+            flag:    .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_part1() {
+        assert_eq!(Ok(1), solve_part1(&program()));
+    }
+
+    #[test]
+    pub fn test_part2() {
+        assert_eq!(Ok(3), solve_part2(&program()));
+    }
+
+    #[test]
+    pub fn test_unknown_destinations() {
+        let source = "
+                    OUT #-5
+                    OUT #1
+                    OUT #2
+                    OUT #50
+                    OUT #3
+                    OUT #4
+                    OUT #255
+                    OUT #5
+                    OUT #6
+            loop:   IN -> [x]
+                    JZ #0, #loop
+            x:      .data 0
+        ";
+        let program = Intcode::new(assemble(source).unwrap());
+        assert_eq!(Ok(6), solve_part1(&program));
+    }
+
+    #[test]
+    pub fn test_idle_without_nat() {
+        // Every computer just waits for packets that never come
+        let source = "
+                    IN -> [x]
+            loop:   IN -> [x]
+                    JZ #0, #loop
+            x:      .data 0
+        ";
+        let program = Intcode::new(assemble(source).unwrap());
+        assert_eq!(Err(NetworkError::Idle), solve_part1(&program));
+        assert_eq!(Err(NetworkError::Idle), solve_part2(&program));
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{HashSet, VecDeque};
+use std::num::ParseIntError;
+
+use crate::intcode::{Budget, Console, Intcode, Response, RunOutcome, VmError};
+
+const PRESSURE_SENSITIVE_FLOOR: &str = "Pressure-Sensitive Floor";
+
+// Some items send the droid into an infinite loop, so no command gets more than this
+const STEPS_PER_COMMAND: u64 = 1_000_000;
+
+#[aoc_generator(day25)]
+pub fn generate_input(input: &str) -> Result<Intcode, ParseIntError> {
+    input.parse()
+}
+
+#[aoc(day25, part1)]
+pub fn solve_part1(program: &Intcode) -> Result<u64, VmError> {
+    let mut droid = Console::new(program.clone());
+    let response = droid.run_with_budget(&mut Budget::steps(STEPS_PER_COMMAND))?;
+    let start = parse_rooms(&response)
+        .pop()
+        .expect("the droid didn't start in a room");
+    let ship = explore(&droid, start)?;
+
+    // Pick up every safe item on the way to the security checkpoint
+    let mut items = Vec::new();
+    for room in &ship.rooms {
+        if room.safe_items.is_empty() {
+            continue;
+        }
+        for direction in &room.path {
+            command(&mut droid, direction)?;
+        }
+        for item in &room.safe_items {
+            command(&mut droid, &format!("take {}", item))?;
+            items.push(item);
+        }
+        for direction in room.path.iter().rev() {
+            command(&mut droid, opposite(direction))?;
+        }
+    }
+    let (checkpoint, door) = ship
+        .checkpoint
+        .expect("the ship doesn't have a pressure-sensitive floor");
+    for direction in &ship.rooms[checkpoint].path {
+        command(&mut droid, direction)?;
+    }
+
+    // Try every combination of items on the floor until one weighs the right amount
+    for subset in 0..1 << items.len() {
+        let mut attempt = droid.clone();
+        for (i, item) in items.iter().enumerate() {
+            if subset & 1 << i == 0 {
+                command(&mut attempt, &format!("drop {}", item))?;
+            }
+        }
+        let response = command(&mut attempt, &door)?;
+        if response.outcome == RunOutcome::Halted {
+            return Ok(find_password(&response).expect("the droid didn't say the password"));
+        }
+    }
+    panic!("no combination of items gets the droid past the pressure-sensitive floor")
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+struct Room {
+    name: String,
+    doors: Vec<String>,
+    items: Vec<String>,
+}
+
+// The lists in a room description
+enum List {
+    Doors,
+    Items,
+}
+
+struct Ship {
+    rooms: Vec<ExploredRoom>,
+    // The room next to the pressure-sensitive floor, and the door leading to it
+    checkpoint: Option<(usize, String)>,
+}
+
+struct ExploredRoom {
+    // The directions leading here from where the droid started
+    path: Vec<String>,
+    safe_items: Vec<String>,
+}
+
+// Maps the ship breadth-first, keeping a copy of the droid in every room it finds so
+// each door only needs to be tried once
+fn explore(droid: &Console, start: Room) -> Result<Ship, VmError> {
+    let mut ship = Ship {
+        rooms: Vec::new(),
+        checkpoint: None,
+    };
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(start.name.clone());
+    queue.push_back((droid.clone(), start, Vec::new()));
+    while let Some((droid, room, path)) = queue.pop_front() {
+        let mut safe_items = Vec::new();
+        for item in &room.items {
+            if is_safe(&droid, &room, item)? {
+                safe_items.push(item.clone());
+            }
+        }
+        let index = ship.rooms.len();
+        for door in &room.doors {
+            let mut next = droid.clone();
+            let rooms = parse_rooms(&command(&mut next, door)?);
+            if rooms.iter().any(|r| r.name == PRESSURE_SENSITIVE_FLOOR) {
+                // Without the right items the droid is thrown back out again
+                ship.checkpoint = Some((index, door.clone()));
+                continue;
+            }
+            if let Some(next_room) = rooms.into_iter().last() {
+                if seen.insert(next_room.name.clone()) {
+                    let mut next_path = path.clone();
+                    next_path.push(door.clone());
+                    queue.push_back((next, next_room, next_path));
+                }
+            }
+        }
+        ship.rooms.push(ExploredRoom { path, safe_items });
+    }
+    Ok(ship)
+}
+
+// An item is safe if the droid can pick it up and still walk around afterwards
+fn is_safe(droid: &Console, room: &Room, item: &str) -> Result<bool, VmError> {
+    let mut droid = droid.clone();
+    let response = command(&mut droid, &format!("take {}", item))?;
+    if response.outcome != RunOutcome::NeedsInput {
+        return Ok(false);
+    }
+    let response = command(&mut droid, &room.doors[0])?;
+    Ok(response.outcome == RunOutcome::NeedsInput && !parse_rooms(&response).is_empty())
+}
+
+fn command(droid: &mut Console, command: &str) -> Result<Response, VmError> {
+    droid.send_line(command);
+    droid.run_with_budget(&mut Budget::steps(STEPS_PER_COMMAND))
+}
+
+// Parses every room description in the droid's output, in order
+fn parse_rooms(response: &Response) -> Vec<Room> {
+    let mut rooms: Vec<Room> = Vec::new();
+    let mut list = None;
+    for line in &response.lines {
+        if line.starts_with("== ") && line.ends_with(" ==") {
+            rooms.push(Room {
+                name: line[3..line.len() - 3].to_string(),
+                ..Default::default()
+            });
+            list = None;
+        } else if line == "Doors here lead:" {
+            list = Some(List::Doors);
+        } else if line == "Items here:" {
+            list = Some(List::Items);
+        } else if let (Some(entry), Some(room)) = (line.strip_prefix("- "), rooms.last_mut()) {
+            match list {
+                Some(List::Doors) => room.doors.push(entry.to_string()),
+                Some(List::Items) => room.items.push(entry.to_string()),
+                None => {}
+            }
+        } else {
+            list = None;
+        }
+    }
+    rooms
+}
+
+fn find_password(response: &Response) -> Option<u64> {
+    response
+        .lines
+        .iter()
+        .flat_map(|line| line.split_whitespace())
+        .find_map(|word| word.parse().ok())
+}
+
+fn opposite(direction: &str) -> &'static str {
+    match direction {
+        "north" => "south",
+        "south" => "north",
+        "east" => "west",
+        "west" => "east",
+        _ => panic!("unknown direction {}", direction),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // A two-room adventure. The hull breach has a lamp and a bomb that blows the droid
+    // up, the checkpoint has a mug, and only the lamp by itself gets past the floor.
+    fn adventure() -> Intcode {
+        let source = r#"
+                    JZ #0, #look
+
+            ; Prints the string at [arg], then jumps to [ret]
+            print:  ARB [arg]
+                    ADD [arg], #0 -> [pos]
+            ploop:  JZ [rb], #pdone
+                    OUT [rb]
+                    ARB #1
+                    ADD [pos], #1 -> [pos]
+                    JZ #0, #ploop
+            pdone:  MUL [pos], #-1 -> [pos]
+                    ARB [pos]
+                    JZ #0, [ret]
+
+            look:   JNZ [room], #lookb
+                    ADD #hull, #0 -> [arg]
+                    ADD #looka2, #0 -> [ret]
+                    JZ #0, #print
+            looka2: JZ [lamp_here], #looka3
+                    ADD #lamp_item, #0 -> [arg]
+                    ADD #looka3, #0 -> [ret]
+                    JZ #0, #print
+            looka3: ADD #bomb_item, #0 -> [arg]
+                    ADD #prompt, #0 -> [ret]
+                    JZ #0, #print
+            lookb:  ADD #checkpoint, #0 -> [arg]
+                    ADD #lookb2, #0 -> [ret]
+                    JZ #0, #print
+            lookb2: JZ [mug_here], #prompt
+                    ADD #mug_item, #0 -> [arg]
+                    ADD #prompt, #0 -> [ret]
+                    JZ #0, #print
+            prompt: ADD #command, #0 -> [arg]
+                    ADD #read, #0 -> [ret]
+                    JZ #0, #print
+
+            ; Reads a line, keeping its first and sixth characters
+            read:   ADD #0, #0 -> [len]
+            rloop:  IN -> [ch]
+                    EQ [ch], #10 -> [flag]
+                    JNZ [flag], #dispatch
+                    JNZ [len], #rnext
+                    ADD [ch], #0 -> [c0]
+            rnext:  EQ [len], #5 -> [flag]
+                    JZ [flag], #rcount
+                    ADD [ch], #0 -> [c5]
+            rcount: ADD [len], #1 -> [len]
+                    JZ #0, #rloop
+            dispatch: EQ [c0], #110 -> [flag]
+                    JNZ [flag], #north
+                    EQ [c0], #115 -> [flag]
+                    JNZ [flag], #south
+                    EQ [c0], #119 -> [flag]
+                    JNZ [flag], #west
+                    EQ [c0], #116 -> [flag]
+                    JNZ [flag], #take
+                    EQ [c0], #100 -> [flag]
+                    JNZ [flag], #drop
+                    JZ #0, #prompt
+
+            north:  JNZ [room], #prompt
+                    ADD #1, #0 -> [room]
+                    JZ #0, #look
+            south:  JZ [room], #prompt
+                    ADD #0, #0 -> [room]
+                    JZ #0, #look
+            west:   JZ [room], #prompt
+                    JZ [lamp], #eject
+                    JNZ [mug], #eject
+                    ADD #win, #0 -> [arg]
+                    ADD #end, #0 -> [ret]
+                    JZ #0, #print
+            eject:  ADD #ejected, #0 -> [arg]
+                    ADD #look, #0 -> [ret]
+                    JZ #0, #print
+            take:   EQ [c5], #98 -> [flag]
+                    JNZ [flag], #boom
+                    EQ [c5], #108 -> [flag]
+                    JZ [flag], #takemug
+                    ADD #1, #0 -> [lamp]
+                    ADD #0, #0 -> [lamp_here]
+                    JZ #0, #prompt
+            takemug: ADD #1, #0 -> [mug]
+                    ADD #0, #0 -> [mug_here]
+                    JZ #0, #prompt
+            boom:   ADD #explode, #0 -> [arg]
+                    ADD #end, #0 -> [ret]
+                    JZ #0, #print
+            end:    HALT
+            drop:   EQ [c5], #108 -> [flag]
+                    JZ [flag], #dropmug
+                    ADD #0, #0 -> [lamp]
+                    JZ #0, #prompt
+            dropmug: ADD #0, #0 -> [mug]
+                    JZ #0, #prompt
+
+            room:   .data 0
+            lamp:   .data 0
+            mug:    .data 0
+            lamp_here: .data 1
+            mug_here: .data 1
+            arg:    .data 0
+            ret:    .data 0
+            pos:    .data 0
+            len:    .data 0
+            ch:     .data 0
+            c0:     .data 0
+            c5:     .data 0
+            flag:   .data 0
+            hull:   .data "\n== Hull Breach ==\nDoors here lead:\n- north\n\nItems here:\n", 0
+            lamp_item: .data "- lamp\n", 0
+            bomb_item: .data "- bomb\n", 0
+            checkpoint:
+                    .data "\n== Checkpoint ==\nDoors here lead:\n- south\n- west\n"
+                    .data "\nItems here:\n", 0
+            mug_item: .data "- mug\n", 0
+            command: .data "\nCommand?\n", 0
+            ejected: .data "\n== Pressure-Sensitive Floor ==\nAlert! You are too heavy.\n", 0
+            win:    .data "\n== Pressure-Sensitive Floor ==\nType 4242 on the keypad.\n", 0
+            explode: .data "The bomb explodes.\n", 0
+        "#;
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_explore() {
+        let mut droid = Console::new(adventure());
+        let response = droid.run().unwrap();
+        let start = parse_rooms(&response).pop().unwrap();
+        assert_eq!("Hull Breach", start.name);
+        let ship = explore(&droid, start).unwrap();
+        assert_eq!(
+            vec![r#"[] ["lamp"]"#, r#"["north"] ["mug"]"#],
+            ship.rooms
+                .iter()
+                .map(|room| format!("{:?} {:?}", room.path, room.safe_items))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some((1, "west".to_string())), ship.checkpoint);
+    }
+
+    #[test]
+    pub fn test_part1() {
+        assert_eq!(Ok(4242), solve_part1(&adventure()));
+    }
+
+    fn response(text: &str) -> Response {
+        Response {
+            lines: text.lines().map(String::from).collect(),
+            values: Vec::new(),
+            outcome: RunOutcome::NeedsInput,
+        }
+    }
+
+    #[test]
+    pub fn test_parse_rooms() {
+        let rooms = parse_rooms(&response(
+            "
+
+== Hull Breach ==
+You got in through a hole in the floor here.
+
+Doors here lead:
+- north
+- east
+
+Items here:
+- mouse
+- whirled peas
+
+Command?
+",
+        ));
+        assert_eq!(
+            vec![Room {
+                name: "Hull Breach".to_string(),
+                doors: vec!["north".to_string(), "east".to_string()],
+                items: vec!["mouse".to_string(), "whirled peas".to_string()],
+            }],
+            rooms
+        );
+    }
+
+    #[test]
+    pub fn test_find_password() {
+        let text = "A loud, robotic voice says \"Analysis complete! You may proceed.\" and you \
+                    enter the cockpit.
+Santa notices your small droid, looks puzzled for a moment, realizes what has happened, and \
+                    radios your ship directly.
+\"Oh, hello! You should be able to get in by typing 2622472 on the keypad at the main airlock.\"";
+        assert_eq!(Some(2622472), find_password(&response(text)));
+    }
+}
@@ -1,14 +1,60 @@
 use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
 use std::mem;
 use std::num::ParseIntError;
+use std::ops::Range;
 use std::str::FromStr;
+use std::sync::Arc;
+
+use self::memory::Memory;
+
+mod ascii;
+mod assembler;
+mod budget;
+mod control_flow;
+mod debugger;
+mod disassembler;
+mod driver;
+mod fuzz;
+mod instruction_set;
+mod memory;
+mod network;
+mod profiler;
+mod snapshot;
+mod symbolic;
+mod threaded;
+mod trace;
+mod transcript;
+mod transpiler;
+
+pub use self::ascii::{Console, Response};
+pub use self::assembler::{assemble, assemble_with, AssemblyError};
+pub use self::budget::Budget;
+pub use self::control_flow::{
+    control_flow_graph, control_flow_graph_with, BasicBlock, ControlFlowGraph, Exit, Target,
+};
+pub use self::debugger::{Debugger, MemoryAccess, Rewind, StopReason, Watch};
+pub use self::disassembler::{disassemble, disassemble_from, disassemble_with, Entry, Listing};
+pub use self::driver::{Driver, Outputs};
+pub use self::fuzz::{FuzzCase, FuzzFailure};
+pub use self::instruction_set::{Execution, Extension, InstructionSet, OpcodeError};
+pub use self::memory::MemoryModel;
+pub use self::network::Network;
+pub use self::profiler::Profile;
+pub use self::symbolic::{LinearExpr, SymbolicError, SymbolicIntcode};
+pub use self::threaded::{Machine, NonBlocking, Stopped};
+pub use self::trace::{MemoryWrite, ResolvedOperand, TraceEvent, TraceWriter, Tracer};
+pub use self::transcript::{Divergence, IoEvent, Transcript};
+pub use self::transpiler::transpile;
 
 #[derive(Clone)]
 pub struct Intcode {
-    memory: Vec<i64>,
+    memory: Memory,
     ip: usize,
     relative_base: i64,
     state: State,
+    instructions: Arc<InstructionSet>,
 }
 
 pub trait Input {
@@ -24,15 +70,36 @@ pub struct IoBus {
     values: VecDeque<i64>,
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum VmError {
+    UnknownOpcode { opcode: i64, ip: usize },
+    InvalidParameterMode { mode: i64, ip: usize },
+    WriteToImmediate { ip: usize },
+    NegativeAddress { address: i64, ip: usize },
+    NegativeJump { target: i64, ip: usize },
+    MemoryLimitExceeded { address: usize, ip: usize },
+}
+
+// Why a program stopped running
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum RunOutcome {
+    Halted,
+    // Running again once input is available continues from the instruction that needed it
+    NeedsInput,
+    // Only returned by `run`, which stops after every output
+    Output(i64),
+    BudgetExhausted,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 enum State {
     Running,
     WaitingForInput(Op),
     Halted,
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug)]
-enum Opcode {
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum Opcode {
     Add,
     Multiply,
     Input,
@@ -43,12 +110,24 @@ enum Opcode {
     Equals,
     RelativeBaseOffset,
     Halt,
+    // An opcode added by an `InstructionSet`
+    Extended(Extension),
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+const MAX_PARAMETERS: usize = 3;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
 struct Op {
     opcode: Opcode,
-    parameters: Vec<Parameter>,
+    // Only the first `opcode.parameter_count()` parameters are meaningful
+    parameters: [Parameter; MAX_PARAMETERS],
+}
+
+// Everything that can be decoded from an instruction word by itself
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+struct Header {
+    opcode: Opcode,
+    modes: [ParameterMode; MAX_PARAMETERS],
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -65,156 +144,498 @@ struct Parameter {
 }
 
 impl Intcode {
-    pub fn execute(&mut self) {
-        self.execute_with_io(|| 0, |_| {});
+    pub fn new(memory: Vec<i64>) -> Self {
+        Self {
+            memory: Memory::new(&memory),
+            ip: 0,
+            relative_base: 0,
+            state: State::Running,
+            instructions: Arc::new(InstructionSet::default()),
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<RunOutcome, VmError> {
+        self.execute_with_io(|| 0, |_| {})
+    }
+
+    // Runs until the program halts or needs input that isn't available
+    pub fn execute_with_io<I: Input, O: Output>(
+        &mut self,
+        input: I,
+        output: O,
+    ) -> Result<RunOutcome, VmError> {
+        self.execute_traced(input, output, &mut ())
+    }
+
+    // Like `execute_with_io`, but reports every executed instruction to `tracer`
+    pub fn execute_traced<I: Input, O: Output, T: Tracer>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+        tracer: &mut T,
+    ) -> Result<RunOutcome, VmError> {
+        while self.step_traced(&mut input, &mut output, tracer)? {}
+        Ok(self.stopped())
+    }
+
+    // Runs until the program produces an output, halts or needs input that isn't available
+    pub fn run<I: Input>(&mut self, input: I) -> Result<RunOutcome, VmError> {
+        self.run_with_budget(input, &mut Budget::unlimited())
+    }
+
+    // Runs instructions from the given set from now on, so that the program can use
+    // the opcodes it adds
+    pub fn with_instruction_set(mut self, instructions: InstructionSet) -> Self {
+        self.instructions = Arc::new(instructions);
+        self
+    }
+
+    pub fn instruction_set(&self) -> &InstructionSet {
+        &self.instructions
+    }
+
+    // Uses the given memory model from now on, keeping the contents of memory
+    pub fn with_memory_model(mut self, model: MemoryModel) -> Self {
+        self.memory.set_model(model);
+        self
+    }
+
+    // Makes writes fail with `MemoryLimitExceeded` rather than take memory beyond roughly
+    // `words` words. A dense VM needs as many words as the highest address it writes, a
+    // sparse one only as many as the pages it writes to. The default limit of 2^24 words
+    // is far more than any puzzle needs.
+    pub fn with_memory_limit(mut self, words: usize) -> Self {
+        self.memory.set_limit(words);
+        self
+    }
+
+    pub fn memory_model(&self) -> MemoryModel {
+        self.memory.model()
+    }
+
+    pub fn memory_limit(&self) -> usize {
+        self.memory.limit()
+    }
+
+    // How many words of memory count against the limit so far
+    pub fn memory_footprint(&self) -> usize {
+        self.memory.footprint()
+    }
+
+    // A copy of memory up to the highest address written. Beware of using this on a
+    // program that wrote to far addresses of sparse memory, `memory_range` only copies
+    // the part asked for.
+    pub fn memory(&self) -> Vec<i64> {
+        self.memory.to_vec()
+    }
+
+    // A copy of a range of memory, reading memory that hasn't been written as zero
+    pub fn memory_range(&self, range: Range<usize>) -> Vec<i64> {
+        range.map(|address| self.memory.read(address)).collect()
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
     }
 
-    pub fn execute_with_io<I: Input, O: Output>(&mut self, mut input: I, mut output: O) {
-        while self.step(&mut input, &mut output) {}
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
     }
 
     pub fn is_halted(&self) -> bool {
         self.state == State::Halted
     }
 
-    pub fn mem_access(&mut self, address: i64) -> &mut i64 {
-        if address < 0 {
-            panic!("Oh no, address is less than 0: {}", address);
-        }
-        if address as usize >= self.memory.len() {
-            self.memory.resize(
-                std::cmp::max(address as usize + 1, self.memory.len() * 2),
-                0,
-            );
+    // The outcome for a program that has stopped running
+    fn stopped(&self) -> RunOutcome {
+        if self.is_halted() {
+            RunOutcome::Halted
+        } else {
+            RunOutcome::NeedsInput
         }
-        self.memory.get_mut(address as usize).unwrap()
-    }
-
-    fn op(&mut self) -> Op {
-        let val = *self.mem_access(self.ip as i64) as u32;
-        let (opcode, param_count) = match val % 100 {
-            1 => (Opcode::Add, 3),
-            2 => (Opcode::Multiply, 3),
-            3 => (Opcode::Input, 1),
-            4 => (Opcode::Output, 1),
-            5 => (Opcode::JumpIfTrue, 2),
-            6 => (Opcode::JumpIfFalse, 2),
-            7 => (Opcode::LessThan, 3),
-            8 => (Opcode::Equals, 3),
-            9 => (Opcode::RelativeBaseOffset, 1),
-            99 => (Opcode::Halt, 0),
-            _ => panic!("invalid operation"),
-        };
-        let mut param_modes = val / 100;
-        let mut parameters = Vec::with_capacity(param_count);
-        for i in 1..=param_count {
-            parameters.push(Parameter {
-                mode: match param_modes % 10 {
-                    0 => ParameterMode::Position,
-                    1 => ParameterMode::Immediate,
-                    2 => ParameterMode::Relative,
-                    _ => panic!("invalid parameter mode"),
-                },
-                value: *self.mem_access((self.ip + i) as i64),
+    }
+
+    pub fn mem_access(&mut self, address: i64) -> Result<&mut i64, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
             });
-            param_modes /= 10;
         }
-        self.ip += 1 + param_count;
-        Op { opcode, parameters }
+        let ip = self.ip;
+        self.memory
+            .word_mut(address as usize)
+            .ok_or(VmError::MemoryLimitExceeded {
+                address: address as usize,
+                ip,
+            })
+    }
+
+    fn op(&mut self) -> Result<Op, VmError> {
+        let ip = self.ip;
+        let header = match self.memory.header(ip) {
+            Ok(header) => header,
+            // Memory only caches built-in instructions, so extensions are decoded each time
+            Err(VmError::UnknownOpcode { opcode, .. }) => self.instructions.decode(opcode, ip)?,
+            Err(e) => return Err(e),
+        };
+        Ok(header.with_parameters(|i| self.memory.read(ip + 1 + i)))
     }
 
-    fn step<I: Input, O: Output>(&mut self, input: &mut I, output: &mut O) -> bool {
+    fn step_traced<I: Input, O: Output, T: Tracer>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        tracer: &mut T,
+    ) -> Result<bool, VmError> {
         if self.state == State::Halted {
-            return false;
+            return Ok(false);
         }
 
-        // If we saved an operation while waiting for input, use that, otherwise read a new op
-        let op =
-            if let State::WaitingForInput(lastop) = mem::replace(&mut self.state, State::Running) {
-                lastop
-            } else {
-                self.op()
-            };
-
-        // Check for halt
-        if op.opcode == Opcode::Halt {
-            self.state = State::Halted;
-            return false;
-        }
+        // If we saved an operation while waiting for input, use that, otherwise read a new op.
+        // The instruction pointer stays on the current instruction until it has executed, so
+        // errors (and a pending input) always refer to the instruction that caused them.
+        let op = match mem::replace(&mut self.state, State::Running) {
+            State::WaitingForInput(lastop) => lastop,
+            _ => self.op()?,
+        };
+        let mut next_ip = self.ip + 1 + op.opcode.parameter_count();
+        let mut event = if T::ENABLED {
+            Some(self.begin_trace(&op))
+        } else {
+            None
+        };
 
-        // Execute the op
+        // Execute the op. Operands are traced as they are loaded, so the event only shows
+        // what the instruction actually read.
+        let params = op.parameters;
         match op.opcode {
             Opcode::Add => {
-                let val = self.load(op.parameters[0]) + self.load(op.parameters[1]);
-                self.write(op.parameters[2], val);
+                let val = self.load_traced(params[0], &mut event)?
+                    + self.load_traced(params[1], &mut event)?;
+                self.write_traced(params[2], val, &mut event)?;
             }
             Opcode::Multiply => {
-                let val = self.load(op.parameters[0]) * self.load(op.parameters[1]);
-                self.write(op.parameters[2], val);
+                let val = self.load_traced(params[0], &mut event)?
+                    * self.load_traced(params[1], &mut event)?;
+                self.write_traced(params[2], val, &mut event)?;
             }
             Opcode::Input => match input.get_input() {
                 None => {
                     self.state = State::WaitingForInput(op);
-                    return false;
+                    return Ok(false);
+                }
+                Some(input) => {
+                    self.write_traced(params[0], input, &mut event)?;
+                    if let Some(event) = event.as_mut() {
+                        event.inputs.push(input);
+                    }
                 }
-                Some(input) => self.write(op.parameters[0], input),
             },
-            Opcode::Output => output.receive_output(self.load(op.parameters[0])),
-            Opcode::JumpIfTrue => {
-                if self.load(op.parameters[0]) != 0 {
-                    self.ip = self.load(op.parameters[1]) as usize;
+            Opcode::Output => {
+                let val = self.load_traced(params[0], &mut event)?;
+                output.receive_output(val);
+                if let Some(event) = event.as_mut() {
+                    event.outputs.push(val);
                 }
             }
-            Opcode::JumpIfFalse => {
-                if self.load(op.parameters[0]) == 0 {
-                    self.ip = self.load(op.parameters[1]) as usize;
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let condition = self.load_traced(params[0], &mut event)? != 0;
+                if condition == (op.opcode == Opcode::JumpIfTrue) {
+                    let target = self.load_traced(params[1], &mut event)?;
+                    next_ip = self.jump_target(target)?;
                 }
             }
             Opcode::LessThan => {
-                let val = if self.load(op.parameters[0]) < self.load(op.parameters[1]) {
+                let val = if self.load_traced(params[0], &mut event)?
+                    < self.load_traced(params[1], &mut event)?
+                {
                     1
                 } else {
                     0
                 };
-                self.write(op.parameters[2], val);
+                self.write_traced(params[2], val, &mut event)?;
             }
             Opcode::Equals => {
-                let val = if self.load(op.parameters[0]) == self.load(op.parameters[1]) {
+                let val = if self.load_traced(params[0], &mut event)?
+                    == self.load_traced(params[1], &mut event)?
+                {
                     1
                 } else {
                     0
                 };
-                self.write(op.parameters[2], val);
+                self.write_traced(params[2], val, &mut event)?;
             }
             Opcode::RelativeBaseOffset => {
-                self.relative_base += self.load(op.parameters[0]);
+                self.relative_base += self.load_traced(params[0], &mut event)?;
+            }
+            Opcode::Halt => self.state = State::Halted,
+            Opcode::Extended(extension) => {
+                let instructions = Arc::clone(&self.instructions);
+                let mut execution = Execution::new(self, op, input, output, &mut event, next_ip);
+                instructions.execute(extension, &mut execution)?;
+                if execution.is_waiting() {
+                    self.state = State::WaitingForInput(op);
+                    return Ok(false);
+                }
+                next_ip = execution.next_ip();
             }
-            _ => unimplemented!(),
         };
-        true
+
+        if let Some(event) = event {
+            tracer.trace(&event);
+        }
+        if self.state == State::Halted {
+            return Ok(false);
+        }
+        self.ip = next_ip;
+        Ok(true)
     }
 
-    fn load(&mut self, parameter: Parameter) -> i64 {
-        match parameter.mode {
-            ParameterMode::Position => *self.mem_access(parameter.value),
-            ParameterMode::Immediate => parameter.value,
-            ParameterMode::Relative => *self.mem_access(self.relative_base + parameter.value),
+    // Starts the event for an instruction that is about to execute
+    fn begin_trace(&self, op: &Op) -> TraceEvent {
+        TraceEvent {
+            ip: self.ip,
+            relative_base: self.relative_base,
+            opcode: op.opcode,
+            operands: Vec::with_capacity(op.parameters().len()),
+            writes: Vec::new(),
+            inputs: Vec::new(),
+            outputs: Vec::new(),
         }
     }
 
-    fn write(&mut self, destination: Parameter, value: i64) {
-        match destination.mode {
-            ParameterMode::Position => *self.mem_access(destination.value) = value,
-            ParameterMode::Relative => {
-                *self.mem_access(self.relative_base + destination.value) = value;
-            }
-            ParameterMode::Immediate => {
-                panic!("immediate parameter mode not supported for write");
-            }
+    // Like `load`, but adds the operand to `event`, if there is one
+    fn load_traced(
+        &self,
+        parameter: Parameter,
+        event: &mut Option<TraceEvent>,
+    ) -> Result<i64, VmError> {
+        let address = self.address(parameter)?;
+        let value = match address {
+            Some(address) => self.memory.read(address),
+            None => parameter.value,
+        };
+        if let Some(event) = event.as_mut() {
+            event.operands.push(ResolvedOperand { address, value });
+        }
+        Ok(value)
+    }
+
+    // Like `write`, but records the write in `event`, if there is one
+    fn write_traced(
+        &mut self,
+        destination: Parameter,
+        value: i64,
+        event: &mut Option<TraceEvent>,
+    ) -> Result<(), VmError> {
+        let old_value = match (event.is_some(), self.address(destination)) {
+            (true, Ok(Some(address))) => Some((address, self.memory.read(address))),
+            _ => None,
+        };
+        self.write(destination, value)?;
+        if let (Some(event), Some((address, old_value))) = (event.as_mut(), old_value) {
+            event.writes.push(MemoryWrite {
+                address,
+                old_value,
+                value,
+            });
+        }
+        Ok(())
+    }
+
+    // The address a parameter refers to, or `None` for immediate parameters
+    fn address(&self, parameter: Parameter) -> Result<Option<usize>, VmError> {
+        let address = match parameter.mode {
+            ParameterMode::Position => parameter.value,
+            ParameterMode::Relative => self.relative_base + parameter.value,
+            ParameterMode::Immediate => return Ok(None),
+        };
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
+            });
+        }
+        Ok(Some(address as usize))
+    }
+
+    fn write(&mut self, destination: Parameter, value: i64) -> Result<(), VmError> {
+        match self.address(destination)? {
+            Some(address) if self.memory.write(address, value) => Ok(()),
+            Some(address) => Err(VmError::MemoryLimitExceeded {
+                address,
+                ip: self.ip,
+            }),
+            None => Err(VmError::WriteToImmediate { ip: self.ip }),
+        }
+    }
+
+    fn jump_target(&self, target: i64) -> Result<usize, VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.ip,
+            });
+        }
+        Ok(target as usize)
+    }
+}
+
+impl Opcode {
+    const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Multiply,
+        Opcode::Input,
+        Opcode::Output,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::RelativeBaseOffset,
+        Opcode::Halt,
+    ];
+
+    // The built-in opcode with the given number
+    pub fn from_value(value: i64) -> Option<Opcode> {
+        match value {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Multiply),
+            3 => Some(Opcode::Input),
+            4 => Some(Opcode::Output),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::RelativeBaseOffset),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn value(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Multiply => 2,
+            Opcode::Input => 3,
+            Opcode::Output => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::RelativeBaseOffset => 9,
+            Opcode::Halt => 99,
+            Opcode::Extended(extension) => extension.value(),
+        }
+    }
+
+    pub fn parameter_count(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::Input | Opcode::Output | Opcode::RelativeBaseOffset => 1,
+            Opcode::Halt => 0,
+            Opcode::Extended(extension) => extension.parameter_count(),
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Multiply => "MUL",
+            Opcode::Input => "IN",
+            Opcode::Output => "OUT",
+            Opcode::JumpIfTrue => "JNZ",
+            Opcode::JumpIfFalse => "JZ",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::RelativeBaseOffset => "ARB",
+            Opcode::Halt => "HALT",
+            Opcode::Extended(extension) => extension.mnemonic(),
+        }
+    }
+
+    // The index of the parameter this opcode writes its result to, if any
+    pub fn write_parameter(self) -> Option<usize> {
+        match self {
+            Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => Some(2),
+            Opcode::Input => Some(0),
+            Opcode::Extended(extension) => extension.write_parameter(),
+            _ => None,
+        }
+    }
+}
+
+impl Header {
+    // Decodes an instruction word with one of the built-in opcodes
+    fn decode(word: i64, ip: usize) -> Result<Header, VmError> {
+        match Opcode::from_value(word % 100) {
+            Some(opcode) => Header::decode_modes(opcode, word, ip),
+            None => Err(VmError::UnknownOpcode { opcode: word, ip }),
+        }
+    }
+
+    // Decodes the parameter modes of an instruction word, given its opcode
+    fn decode_modes(opcode: Opcode, word: i64, ip: usize) -> Result<Header, VmError> {
+        let mut modes = [ParameterMode::Position; MAX_PARAMETERS];
+        let mut mode_digits = word / 100;
+        for mode in modes.iter_mut().take(opcode.parameter_count()) {
+            *mode = match mode_digits % 10 {
+                0 => ParameterMode::Position,
+                1 => ParameterMode::Immediate,
+                2 => ParameterMode::Relative,
+                digit => return Err(VmError::InvalidParameterMode { mode: digit, ip }),
+            };
+            mode_digits /= 10;
+        }
+        Ok(Header { opcode, modes })
+    }
+
+    // Combines the header with parameter values, given a function to look up the
+    // value of the parameter with a particular index
+    fn with_parameters<F: Fn(usize) -> i64>(self, value: F) -> Op {
+        let mut parameters = [Parameter::default(); MAX_PARAMETERS];
+        for (i, parameter) in parameters
+            .iter_mut()
+            .enumerate()
+            .take(self.opcode.parameter_count())
+        {
+            *parameter = Parameter {
+                mode: self.modes[i],
+                value: value(i),
+            };
+        }
+        Op {
+            opcode: self.opcode,
+            parameters,
+        }
+    }
+}
+
+impl Op {
+    fn parameters(&self) -> &[Parameter] {
+        &self.parameters[..self.opcode.parameter_count()]
+    }
+}
+
+impl Default for Parameter {
+    fn default() -> Self {
+        Parameter {
+            mode: ParameterMode::Position,
+            value: 0,
         }
     }
 }
 
+// Decodes the instruction at `ip`. Memory past the end of `memory` reads as zero,
+// just like it does for a running program.
+fn decode(memory: &[i64], ip: usize, instructions: &InstructionSet) -> Result<Op, VmError> {
+    let word = |address: usize| memory.get(address).copied().unwrap_or(0);
+    Ok(instructions
+        .decode(word(ip), ip)?
+        .with_parameters(|i| word(ip + 1 + i)))
+}
+
 impl<T> Input for T
 where
     T: FnMut() -> i64,
@@ -242,6 +663,14 @@ impl IoBus {
         self.values.push_back(value);
     }
 
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
     pub fn read_str(&mut self) -> String {
         let mut s = String::new();
         while let Some(c) = self.read() {
@@ -293,17 +722,43 @@ impl Output for &mut IoBus {
     }
 }
 
+impl Display for VmError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            VmError::UnknownOpcode { opcode, ip } => {
+                write!(f, "unknown opcode {} at ip {}", opcode, ip)
+            }
+            VmError::InvalidParameterMode { mode, ip } => {
+                write!(f, "invalid parameter mode {} at ip {}", mode, ip)
+            }
+            VmError::WriteToImmediate { ip } => {
+                write!(f, "write to immediate mode parameter at ip {}", ip)
+            }
+            VmError::NegativeAddress { address, ip } => {
+                write!(f, "access to negative address {} at ip {}", address, ip)
+            }
+            VmError::NegativeJump { target, ip } => {
+                write!(f, "jump to negative ip {} at ip {}", target, ip)
+            }
+            VmError::MemoryLimitExceeded { address, ip } => {
+                write!(
+                    f,
+                    "write to address {} at ip {} exceeds the memory limit",
+                    address, ip
+                )
+            }
+        }
+    }
+}
+
+impl Error for VmError {}
+
 impl FromStr for Intcode {
     type Err = ParseIntError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let code: Result<Vec<i64>, ParseIntError> = s.split(',').map(str::parse).collect();
-        Ok(Self {
-            memory: code?,
-            ip: 0,
-            relative_base: 0,
-            state: State::Running,
-        })
+        Ok(Self::new(code?))
     }
 }
 
@@ -313,25 +768,153 @@ mod tests {
 
     #[test]
     pub fn test_step() {
-        let mut program = Intcode {
-            memory: vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50],
-            ip: 0,
-            relative_base: 0,
-            state: State::Running,
-        };
+        let mut program = Intcode::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
 
-        program.step(&mut || 0, &mut |_| {});
+        program
+            .step_traced(&mut || 0, &mut |_| {}, &mut ())
+            .unwrap();
         assert_eq!(
             vec![1, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
-            program.memory
+            program.memory()
         );
         assert_eq!(4, program.ip);
 
-        program.step(&mut || 0, &mut |_| {});
+        program
+            .step_traced(&mut || 0, &mut |_| {}, &mut ())
+            .unwrap();
         assert_eq!(
             vec![3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
-            program.memory
+            program.memory()
         );
         assert_eq!(8, program.ip);
     }
+
+    #[test]
+    pub fn test_opcodes() {
+        let describe = |opcode: Opcode| {
+            (
+                opcode.value(),
+                opcode.mnemonic(),
+                opcode.parameter_count(),
+                opcode.write_parameter(),
+            )
+        };
+        assert_eq!(
+            Some((7, "LT", 3, Some(2))),
+            Opcode::from_value(7).map(describe)
+        );
+        assert_eq!(
+            Some((5, "JNZ", 2, None)),
+            Opcode::from_value(5).map(describe)
+        );
+        assert_eq!(None, Opcode::from_value(10));
+        for &opcode in &Opcode::ALL {
+            assert_eq!(Some(opcode), Opcode::from_value(opcode.value()));
+        }
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let run = |code: &str| code.parse::<Intcode>().unwrap().execute();
+        assert_eq!(
+            Err(VmError::UnknownOpcode { opcode: 42, ip: 4 }),
+            run("1101,1,1,0,42")
+        );
+        assert_eq!(
+            Err(VmError::InvalidParameterMode { mode: 3, ip: 0 }),
+            run("301,0,0,0,99")
+        );
+        assert_eq!(
+            Err(VmError::WriteToImmediate { ip: 0 }),
+            run("11101,1,1,0,99")
+        );
+        assert_eq!(
+            Err(VmError::NegativeAddress { address: -1, ip: 0 }),
+            run("1,-1,0,0,99")
+        );
+        assert_eq!(
+            Err(VmError::NegativeJump { target: -5, ip: 0 }),
+            run("1105,1,-5,99")
+        );
+    }
+
+    #[test]
+    pub fn test_run_outcomes() {
+        let source = "
+                    IN -> [value]
+                    OUT [value]
+                    OUT #7
+                    HALT
+            value:  .data 0
+        ";
+        let mut program = Intcode::new(assemble(source).unwrap());
+        let mut input = IoBus::default();
+        assert_eq!(Ok(RunOutcome::NeedsInput), program.run(&mut input));
+        input.write(5);
+        assert_eq!(Ok(RunOutcome::Output(5)), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::Output(7)), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::Halted), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::Halted), program.execute());
+    }
+
+    #[test]
+    pub fn test_far_writes() {
+        // Stores 42 at 10^12 and reads it back
+        let program = "21101,0,42,1000000000000,4,1000000000000,99"
+            .parse::<Intcode>()
+            .unwrap();
+        let mut output = Vec::new();
+        let mut sparse = program.clone().with_memory_model(MemoryModel::Sparse);
+        sparse.execute_with_io(|| 0, |v| output.push(v)).unwrap();
+        assert_eq!(vec![42], output);
+        assert!(sparse.memory_footprint() < 1000);
+
+        let mut dense = program.with_memory_limit(1 << 20);
+        assert_eq!(
+            Err(VmError::MemoryLimitExceeded {
+                address: 1_000_000_000_000,
+                ip: 0
+            }),
+            dense.execute()
+        );
+        assert_eq!(0, dense.ip());
+        assert!(dense.mem_access(1 << 30).is_err());
+        assert!(dense.mem_access(1000).is_ok());
+
+        // Even without a limit of its own, a dense VM fails rather than run out of memory
+        let mut default = "21101,0,42,1000000000000,99".parse::<Intcode>().unwrap();
+        assert_eq!(
+            Err(VmError::MemoryLimitExceeded {
+                address: 1_000_000_000_000,
+                ip: 0
+            }),
+            default.execute()
+        );
+    }
+
+    #[test]
+    pub fn test_self_modifying_code() {
+        let source = "
+            start:  OUT #1
+                    ADD [start+1], #1 -> [start+1]
+                    EQ [start+1], #3 -> [flag]
+                    JZ [flag], #start
+                    ADD #99, #0 -> [start]
+                    JZ #0, #start
+            flag:   .data 0
+        ";
+        let mut program = Intcode::new(assemble(source).unwrap());
+        let mut output = Vec::new();
+        program
+            .execute_with_io(
+                || 0,
+                |v| {
+                    assert!(output.len() < 2, "stale instruction executed");
+                    output.push(v);
+                },
+            )
+            .unwrap();
+        assert_eq!(vec![1, 2], output);
+        assert!(program.is_halted());
+    }
 }
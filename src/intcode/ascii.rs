@@ -0,0 +1,258 @@
+use std::io::{self, BufRead, Write};
+use std::mem;
+
+use super::{Budget, Intcode, IoBus, RunOutcome, Transcript, VmError};
+
+// Runs programs that talk in ASCII text, sending them input a line at a time and
+// splitting their output into lines of text and any values outside the ASCII range,
+// which is how ASCII programs usually report their results
+#[derive(Clone)]
+pub struct Console {
+    program: Intcode,
+    input: IoBus,
+    transcript: Option<Transcript>,
+}
+
+// Everything a program output until it stopped
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Response {
+    // Text without the line endings. A line the program didn't finish is included too.
+    pub lines: Vec<String>,
+    pub values: Vec<i64>,
+    pub outcome: RunOutcome,
+}
+
+impl Console {
+    pub fn new(program: Intcode) -> Self {
+        Self {
+            program,
+            input: IoBus::default(),
+            transcript: None,
+        }
+    }
+
+    // Records everything the program reads and outputs from now on, see `transcript`
+    pub fn with_recording(mut self) -> Self {
+        self.transcript.get_or_insert_with(Transcript::new);
+        self
+    }
+
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    pub fn program(&self) -> &Intcode {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Intcode {
+        &mut self.program
+    }
+
+    pub fn into_program(self) -> Intcode {
+        self.program
+    }
+
+    // Queues a line of input, adding the line ending
+    pub fn send_line(&mut self, line: &str) {
+        self.send_text(line);
+        self.input.write(i64::from(b'\n'));
+    }
+
+    // Queues text exactly as given, which may contain several lines
+    pub fn send_text(&mut self, text: &str) {
+        for byte in text.bytes() {
+            self.input.write(i64::from(byte));
+        }
+    }
+
+    // Runs the program until it halts or needs more input than has been sent
+    pub fn run(&mut self) -> Result<Response, VmError> {
+        self.run_with_budget(&mut Budget::unlimited())
+    }
+
+    // Like `run`, but gives up once the budget is exhausted
+    pub fn run_with_budget(&mut self, budget: &mut Budget) -> Result<Response, VmError> {
+        let mut output = Vec::new();
+        let outcome = match &mut self.transcript {
+            Some(transcript) => self.program.execute_traced_with_budget(
+                &mut self.input,
+                |value| output.push(value),
+                transcript,
+                budget,
+            )?,
+            None => self.program.execute_with_budget(
+                &mut self.input,
+                |value| output.push(value),
+                budget,
+            )?,
+        };
+
+        let mut lines = Vec::new();
+        let mut values = Vec::new();
+        let mut line = String::new();
+        for value in output {
+            match value {
+                10 => lines.push(mem::take(&mut line)),
+                0..=127 => line.push(value as u8 as char),
+                _ => values.push(value),
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        Ok(Response {
+            lines,
+            values,
+            outcome,
+        })
+    }
+
+    // Lets a person play the program, reading lines of input from `reader` and writing
+    // output to `writer` until the program halts or the input runs out. A program that
+    // crashes fails with an `io::Error` wrapping the `VmError`.
+    pub fn interact<R: BufRead, W: Write>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+    ) -> io::Result<()> {
+        loop {
+            let response = match self.run() {
+                Ok(response) => response,
+                Err(e) => return Err(io::Error::other(e)),
+            };
+            for line in &response.lines {
+                writeln!(writer, "{}", line)?;
+            }
+            for value in &response.values {
+                writeln!(writer, "{}", value)?;
+            }
+            writer.flush()?;
+            if response.outcome == RunOutcome::Halted {
+                return Ok(());
+            }
+
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            self.send_line(line.trim_end_matches(&['\r', '\n'][..]));
+        }
+    }
+}
+
+impl Response {
+    // The last line of output, if the program is waiting for input after it
+    pub fn prompt(&self) -> Option<&str> {
+        match self.outcome {
+            RunOutcome::NeedsInput => self.lines.last().map(String::as_str),
+            _ => None,
+        }
+    }
+
+    // The lines of text joined back together
+    pub fn text(&self) -> String {
+        self.lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Asks for a name, echoes it back and reports 1000
+    fn greeter() -> Console {
+        let source = r#"
+                    ARB #prompt
+            print:  JZ [rb], #read
+                    OUT [rb]
+                    ARB #1
+                    JZ #0, #print
+            read:   IN -> [char]
+                    EQ [char], #10 -> [flag]
+                    JNZ [flag], #done
+                    OUT [char]
+                    JZ #0, #read
+            done:   OUT #10
+                    OUT #1000
+                    HALT
+            prompt: .data "Welcome\nName? ", 0
+            char:   .data 0
+            flag:   .data 0
+        "#;
+        Console::new(Intcode::new(assemble(source).unwrap()))
+    }
+
+    #[test]
+    pub fn test_run() {
+        let mut console = greeter();
+        let response = console.run().unwrap();
+        assert_eq!(vec!["Welcome", "Name? "], response.lines);
+        assert_eq!(Some("Name? "), response.prompt());
+
+        console.send_line("Ada");
+        let response = console.run().unwrap();
+        assert_eq!(
+            Response {
+                lines: vec!["Ada".to_string()],
+                values: vec![1000],
+                outcome: RunOutcome::Halted
+            },
+            response
+        );
+        assert_eq!(None, response.prompt());
+        assert_eq!("Ada\n", response.text());
+    }
+
+    #[test]
+    pub fn test_budget() {
+        let mut console = greeter();
+        let response = console.run_with_budget(&mut Budget::steps(10)).unwrap();
+        assert_eq!(RunOutcome::BudgetExhausted, response.outcome);
+        assert_eq!(None, response.prompt());
+        assert_eq!(vec!["We"], response.lines);
+    }
+
+    #[test]
+    pub fn test_recording() {
+        let mut console = greeter().with_recording();
+        console.run().unwrap();
+        console.send_line("Al");
+        console.run().unwrap();
+        let transcript = console.transcript().unwrap();
+        assert_eq!(
+            "Al\n".bytes().map(i64::from).collect::<Vec<_>>(),
+            transcript.inputs().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Ok(RunOutcome::Halted),
+            transcript.replay(&mut greeter().into_program())
+        );
+    }
+
+    #[test]
+    pub fn test_interact() {
+        let mut transcript = Vec::new();
+        greeter()
+            .interact("Bob\r\nignored\n".as_bytes(), &mut transcript)
+            .unwrap();
+        assert_eq!(
+            "Welcome\nName? \nBob\n1000\n",
+            String::from_utf8(transcript).unwrap()
+        );
+
+        // Outputs from a negative address once it has read a line
+        let program = assemble("IN -> [0]\nOUT [rb-1]").unwrap();
+        let error = Console::new(Intcode::new(program))
+            .interact("x\n".as_bytes(), io::sink())
+            .unwrap_err();
+        assert_eq!(
+            Some(&VmError::NegativeAddress { address: -1, ip: 2 }),
+            error.get_ref().and_then(|e| e.downcast_ref())
+        );
+    }
+}
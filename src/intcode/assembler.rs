@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use super::{InstructionSet, Opcode, ParameterMode};
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct AssemblyError {
+    line: usize,
+    message: String,
+}
+
+struct Line {
+    number: usize,
+    address: usize,
+    item: Item,
+}
+
+enum Item {
+    Instruction(Opcode, Vec<Operand>),
+    Data(Vec<Expr>),
+}
+
+struct Operand {
+    mode: ParameterMode,
+    value: Expr,
+}
+
+// A sum of signed terms, e.g. `loop+2` or `-5`
+#[derive(Default)]
+struct Expr {
+    terms: Vec<(i64, Term)>,
+}
+
+enum Term {
+    Number(i64),
+    Label(String),
+}
+
+// Assembles a program written in the same syntax the disassembler produces:
+//
+//     start:  IN -> [rb+1]         ; comments run to the end of the line
+//             JZ [rb+1], #start
+//             OUT [message]
+//             HALT
+//     message: .data 1, -2, "hi\n"
+//
+// Operands are `#imm` (immediate), `[addr]` (position) or `[rb+off]` (relative), and
+// the parameter an instruction writes to goes after `->`. Anywhere a number is
+// expected a label (optionally plus or minus an offset) can be used instead.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssemblyError> {
+    assemble_with(source, &InstructionSet::default())
+}
+
+// Assembles a program that can use the opcodes added to `instructions`
+pub fn assemble_with(
+    source: &str,
+    instructions: &InstructionSet,
+) -> Result<Vec<i64>, AssemblyError> {
+    // First pass: parse every line, assigning addresses and collecting labels
+    let mut lines = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = 0;
+    for (i, text) in source.lines().enumerate() {
+        let number = i + 1;
+        let error = |message: String| AssemblyError {
+            line: number,
+            message,
+        };
+        let mut text = strip_comment(text).trim();
+        while let Some((name, rest)) = split_label(text) {
+            if name.chars().all(|ch| ch.is_ascii_digit()) {
+                // A numeric prefix, as in a disassembly listing, just asserts the address
+                if name.parse() != Ok(address) {
+                    return Err(error(format!(
+                        "expected address {}, found {}",
+                        address, name
+                    )));
+                }
+            } else if labels.insert(name.to_owned(), address).is_some() {
+                return Err(error(format!("duplicate label `{}`", name)));
+            }
+            text = rest.trim_start();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let item = parse_item(text, instructions).map_err(error)?;
+        let size = match &item {
+            Item::Instruction(_, operands) => 1 + operands.len(),
+            Item::Data(values) => values.len(),
+        };
+        lines.push(Line {
+            number,
+            address,
+            item,
+        });
+        address += size;
+    }
+
+    // Second pass: resolve labels and encode each line
+    let mut memory = Vec::with_capacity(address);
+    for line in lines {
+        let number = line.number;
+        let error = |message: String| AssemblyError {
+            line: number,
+            message,
+        };
+        debug_assert_eq!(line.address, memory.len());
+        match line.item {
+            Item::Instruction(opcode, operands) => {
+                let mut word = opcode.value();
+                let mut mode_multiplier = 100;
+                for operand in &operands {
+                    word += mode_multiplier * mode_digit(operand.mode);
+                    mode_multiplier *= 10;
+                }
+                memory.push(word);
+                for operand in &operands {
+                    memory.push(operand.value.evaluate(&labels).map_err(error)?);
+                }
+            }
+            Item::Data(values) => {
+                for value in &values {
+                    memory.push(value.evaluate(&labels).map_err(error)?);
+                }
+            }
+        }
+    }
+    Ok(memory)
+}
+
+pub(super) fn mode_digit(mode: ParameterMode) -> i64 {
+    match mode {
+        ParameterMode::Position => 0,
+        ParameterMode::Immediate => 1,
+        ParameterMode::Relative => 2,
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, ch) in text.char_indices() {
+        match ch {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &text[..i],
+            _ => {}
+        }
+    }
+    text
+}
+
+// Splits a leading `name:` off of a line, if there is one
+fn split_label(text: &str) -> Option<(&str, &str)> {
+    let colon = text.find(':')?;
+    let name = &text[..colon];
+    if !name.is_empty() && name.chars().all(is_name_char) {
+        Some((name, &text[colon + 1..]))
+    } else {
+        None
+    }
+}
+
+fn is_name_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_' || ch == '.'
+}
+
+fn parse_item(text: &str, instructions: &InstructionSet) -> Result<Item, String> {
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(i) => (&text[..i], text[i..].trim()),
+        None => (text, ""),
+    };
+    if mnemonic.eq_ignore_ascii_case(".data") || mnemonic.eq_ignore_ascii_case("DATA") {
+        return parse_data(rest).map(Item::Data);
+    }
+    let opcode = instructions
+        .opcode_named(mnemonic)
+        .ok_or_else(|| format!("unknown mnemonic `{}`", mnemonic))?;
+
+    let (sources, destination) = match rest.find("->") {
+        Some(i) => (rest[..i].trim(), Some(rest[i + 2..].trim())),
+        None => (rest, None),
+    };
+    let mut operands = Vec::new();
+    if !sources.is_empty() {
+        for operand in sources.split(',') {
+            operands.push(parse_operand(operand.trim())?);
+        }
+    }
+    let operand_count = operands.len() + destination.iter().count();
+    if operand_count != opcode.parameter_count() {
+        return Err(format!(
+            "{} takes {} operands, found {}",
+            mnemonic,
+            opcode.parameter_count(),
+            operand_count
+        ));
+    }
+    if let Some(destination) = destination {
+        if opcode.write_parameter() != Some(operands.len()) {
+            return Err(format!("{} does not take a destination", mnemonic));
+        }
+        operands.push(parse_operand(destination)?);
+    }
+    if let Some(i) = opcode.write_parameter() {
+        if operands[i].mode == ParameterMode::Immediate {
+            return Err(format!("{} cannot write to an immediate operand", mnemonic));
+        }
+    }
+    Ok(Item::Instruction(opcode, operands))
+}
+
+fn parse_operand(text: &str) -> Result<Operand, String> {
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand {
+            mode: ParameterMode::Immediate,
+            value: parse_expr(value)?,
+        });
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let inner = inner.trim();
+        let is_relative = inner
+            .get(..2)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("rb"))
+            .is_some_and(|_| !inner[2..].starts_with(is_name_char));
+        if is_relative {
+            let offset = inner[2..].trim();
+            return Ok(Operand {
+                mode: ParameterMode::Relative,
+                value: if offset.is_empty() {
+                    Expr::default()
+                } else {
+                    parse_expr(offset)?
+                },
+            });
+        }
+        return Ok(Operand {
+            mode: ParameterMode::Position,
+            value: parse_expr(inner)?,
+        });
+    }
+    Err(format!("invalid operand `{}`", text))
+}
+
+fn parse_expr(text: &str) -> Result<Expr, String> {
+    let mut expr = Expr::default();
+    let mut rest = text.trim();
+    let mut sign = 1;
+    loop {
+        // Any number of unary signs may precede a term
+        while let Some(ch) = rest.chars().next().filter(|&ch| ch == '-' || ch == '+') {
+            if ch == '-' {
+                sign = -sign;
+            }
+            rest = rest[1..].trim_start();
+        }
+        let end = rest.find(|ch| !is_name_char(ch)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        let term = if token.is_empty() {
+            return Err(format!("invalid expression `{}`", text));
+        } else if token.starts_with(|ch: char| ch.is_ascii_digit()) {
+            Term::Number(
+                token
+                    .parse()
+                    .map_err(|_| format!("invalid number `{}`", token))?,
+            )
+        } else {
+            Term::Label(token.to_owned())
+        };
+        expr.terms.push((sign, term));
+        rest = rest[end..].trim_start();
+        sign = match rest.chars().next() {
+            None => return Ok(expr),
+            Some('+') => 1,
+            Some('-') => -1,
+            Some(_) => return Err(format!("invalid expression `{}`", text)),
+        };
+        rest = rest[1..].trim_start();
+    }
+}
+
+fn parse_data(text: &str) -> Result<Vec<Expr>, String> {
+    let mut values = Vec::new();
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        if rest.starts_with('"') {
+            let (s, remainder) = parse_string(rest)?;
+            values.extend(s.chars().map(|ch| Expr {
+                terms: vec![(1, Term::Number(ch as i64))],
+            }));
+            rest = remainder.trim_start();
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            values.push(parse_expr(&rest[..end])?);
+            rest = rest[end..].trim_start();
+        }
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_start();
+            if rest.is_empty() {
+                return Err("trailing comma in data".to_owned());
+            }
+        } else if !rest.is_empty() {
+            return Err(format!("expected `,` before `{}`", rest));
+        }
+    }
+    Ok(values)
+}
+
+// Parses a string literal at the start of `text`, returning it and the remaining text
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut s = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, ch)) = chars.next() {
+        match ch {
+            '"' => return Ok((s, &text[i + 1..])),
+            '\\' => s.push(match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, '0')) => '\0',
+                Some((_, ch @ '"')) | Some((_, ch @ '\\')) => ch,
+                Some((_, ch)) => return Err(format!("unknown escape `\\{}`", ch)),
+                None => break,
+            }),
+            _ => s.push(ch),
+        }
+    }
+    Err("unterminated string literal".to_owned())
+}
+
+impl Expr {
+    fn evaluate(&self, labels: &HashMap<String, usize>) -> Result<i64, String> {
+        let mut value = 0;
+        for (sign, term) in &self.terms {
+            value += sign
+                * match term {
+                    Term::Number(n) => *n,
+                    Term::Label(name) => *labels
+                        .get(name)
+                        .ok_or_else(|| format!("undefined label `{}`", name))?
+                        as i64,
+                };
+        }
+        Ok(value)
+    }
+}
+
+impl Display for AssemblyError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl Error for AssemblyError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{disassemble, Intcode, IoBus};
+
+    #[test]
+    pub fn test_assemble() {
+        let source = "
+            ADD [9], [10] -> [3]
+            MUL [3], [11] -> [0]  ; the example from day 2
+            HALT
+            .data 30, 40, 50
+        ";
+        assert_eq!(
+            Ok(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]),
+            assemble(source)
+        );
+    }
+
+    #[test]
+    pub fn test_labels_and_strings() {
+        let source = r#"
+                    ARB #message
+            loop:   OUT [rb]
+                    ARB #1
+                    JNZ [rb], #loop
+                    HALT
+            message: .data "hi; \"there\"\n", 0
+        "#;
+        let mut program = Intcode::new(assemble(source).unwrap());
+        let mut output = IoBus::default();
+        program.execute_with_io(|| 0, &mut output).unwrap();
+        assert_eq!("hi; \"there\"\n", output.read_str());
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let memory = vec![
+            3, 100, 1008, 100, 0, 101, 1005, 101, 15, 21001, 100, -1, 0, 4, 0, 99, 7,
+        ];
+        let listing = disassemble(&memory).with_labels().to_string();
+        assert_eq!(Ok(memory), assemble(&listing));
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let error = |line, message: &str| {
+            Err(AssemblyError {
+                line,
+                message: message.to_owned(),
+            })
+        };
+        assert_eq!(error(2, "unknown mnemonic `NOP`"), assemble("HALT\nNOP"));
+        assert_eq!(
+            error(1, "ADD takes 3 operands, found 2"),
+            assemble("ADD #1 -> [0]")
+        );
+        assert_eq!(
+            error(1, "IN cannot write to an immediate operand"),
+            assemble("IN -> #5")
+        );
+        assert_eq!(
+            error(1, "undefined label `nowhere`"),
+            assemble("JZ #0, #nowhere")
+        );
+        assert_eq!(
+            error(2, "duplicate label `a`"),
+            assemble("a: HALT\na: HALT")
+        );
+        assert_eq!(
+            error(1, "unterminated string literal"),
+            assemble(".data \"oops")
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+use super::{Input, Intcode, Output, RunOutcome, Tracer, VmError};
+
+// Checking the clock is much slower than executing an instruction, so deadlines are only
+// checked this often
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+// Limits how long `execute_with_budget` or `run_with_budget` may run for. The same
+// budget can be passed to several calls, which then share it.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Budget {
+    max_steps: Option<u64>,
+    deadline: Option<Instant>,
+    pub(super) steps: u64,
+}
+
+impl Budget {
+    pub fn unlimited() -> Self {
+        Self {
+            max_steps: None,
+            deadline: None,
+            steps: 0,
+        }
+    }
+
+    pub fn steps(max_steps: u64) -> Self {
+        Self {
+            max_steps: Some(max_steps),
+            ..Self::unlimited()
+        }
+    }
+
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        self.with_deadline(Instant::now() + timeout)
+    }
+
+    // The number of instructions executed under this budget so far
+    pub fn steps_taken(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn remaining_steps(&self) -> Option<u64> {
+        self.max_steps.map(|max_steps| max_steps - self.steps)
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.max_steps == Some(self.steps)
+            || self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    // Like `is_exhausted`, but only looks at the clock every so often
+    pub(super) fn should_stop(&self) -> bool {
+        self.max_steps == Some(self.steps)
+            || (self.steps % DEADLINE_CHECK_INTERVAL == 0 && self.is_exhausted())
+    }
+}
+
+impl Intcode {
+    // Like `execute_with_io`, but gives up once the budget is exhausted, leaving the
+    // program ready to resume. Instructions that block on input don't count against the
+    // budget.
+    pub fn execute_with_budget<I: Input, O: Output>(
+        &mut self,
+        input: I,
+        output: O,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, VmError> {
+        self.execute_traced_with_budget(input, output, &mut (), budget)
+    }
+
+    // Like `execute_with_budget`, but reports every executed instruction to `tracer`
+    pub fn execute_traced_with_budget<I: Input, O: Output, T: Tracer>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+        tracer: &mut T,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, VmError> {
+        loop {
+            if budget.should_stop() {
+                return Ok(RunOutcome::BudgetExhausted);
+            }
+            if !self.step_traced(&mut input, &mut output, tracer)? {
+                return Ok(self.stopped());
+            }
+            budget.steps += 1;
+        }
+    }
+
+    // Like `run`, but gives up once the budget is exhausted
+    pub fn run_with_budget<I: Input>(
+        &mut self,
+        input: I,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, VmError> {
+        self.run_traced_with_budget(input, &mut (), budget)
+    }
+
+    // Like `run_with_budget`, but reports every executed instruction to `tracer`
+    pub fn run_traced_with_budget<I: Input, T: Tracer>(
+        &mut self,
+        mut input: I,
+        tracer: &mut T,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, VmError> {
+        let mut output = None;
+        loop {
+            if budget.should_stop() {
+                return Ok(RunOutcome::BudgetExhausted);
+            }
+            if !self.step_traced(&mut input, &mut |value| output = Some(value), tracer)? {
+                return Ok(self.stopped());
+            }
+            budget.steps += 1;
+            if let Some(value) = output {
+                return Ok(RunOutcome::Output(value));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, IoBus};
+
+    fn counter() -> Intcode {
+        let source = "
+            loop:   ADD [count], #1 -> [count]
+                    OUT [count]
+                    JNZ #1, #loop
+            count:  .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_step_budget() {
+        let mut program = counter();
+        let mut output = Vec::new();
+        let mut budget = Budget::steps(10);
+        assert_eq!(
+            Ok(RunOutcome::BudgetExhausted),
+            program.execute_with_budget(|| 0, |v| output.push(v), &mut budget)
+        );
+        assert_eq!(vec![1, 2, 3], output);
+        assert_eq!(Some(0), budget.remaining_steps());
+        assert_eq!(4, program.ip());
+
+        // Resuming picks up exactly where the program stopped
+        let mut budget = Budget::steps(3);
+        program
+            .execute_with_budget(|| 0, |v| output.push(v), &mut budget)
+            .unwrap();
+        assert_eq!(vec![1, 2, 3, 4], output);
+        assert_eq!(4, program.ip());
+
+        let mut program = "3,0,99".parse::<Intcode>().unwrap();
+        let mut budget = Budget::steps(1);
+        assert_eq!(
+            Ok(RunOutcome::NeedsInput),
+            program.execute_with_budget(IoBus::default(), |_| {}, &mut budget)
+        );
+        assert_eq!(0, budget.steps_taken());
+    }
+
+    #[test]
+    pub fn test_run_with_budget() {
+        let mut program = counter();
+        let mut budget = Budget::steps(5);
+        assert_eq!(
+            Ok(RunOutcome::Output(1)),
+            program.run_with_budget(|| 0, &mut budget)
+        );
+        assert_eq!(
+            Ok(RunOutcome::Output(2)),
+            program.run_with_budget(|| 0, &mut budget)
+        );
+        assert_eq!(
+            Ok(RunOutcome::BudgetExhausted),
+            program.run_with_budget(|| 0, &mut budget)
+        );
+    }
+
+    #[test]
+    pub fn test_deadline() {
+        let mut budget = Budget::unlimited().with_timeout(Duration::from_millis(10));
+        assert_eq!(
+            Ok(RunOutcome::BudgetExhausted),
+            counter().execute_with_budget(|| 0, |_| {}, &mut budget)
+        );
+        assert!(budget.steps_taken() > 0);
+        assert!(budget.is_exhausted());
+        assert_eq!(None, budget.remaining_steps());
+    }
+}
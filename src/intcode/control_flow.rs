@@ -0,0 +1,388 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+use super::disassembler::{disassemble_window, is_well_formed};
+use super::{decode, InstructionSet, Op, Opcode, ParameterMode};
+
+// The basic blocks reachable from address 0, found by following jumps without running
+// the program. Code that's only reached through computed jumps (such as returns from
+// subroutines) or that the program writes at runtime isn't found.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct ControlFlowGraph {
+    blocks: Vec<BasicBlock>,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BasicBlock {
+    // The address of each instruction in the block, in order
+    pub instructions: Vec<usize>,
+    pub exit: Exit,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Exit {
+    // Runs on into the instruction at the given address. There's no block there if
+    // memory doesn't decode into a valid instruction.
+    Next(usize),
+    // Ends with a jump or an added instruction, which falls through to `next` unless it
+    // always jumps
+    Jump { target: Target, next: Option<usize> },
+    Halt,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Target {
+    Address(usize),
+    // Loaded from memory, so the target is only known when the program runs
+    Computed,
+    // An immediate target the program can't jump to
+    Negative(i64),
+}
+
+// Builds the control-flow graph of a memory image. Conditional jumps whose condition is
+// an immediate value are treated as always or never taken.
+pub fn control_flow_graph(memory: &[i64]) -> ControlFlowGraph {
+    control_flow_graph_with(memory, &InstructionSet::default())
+}
+
+// Builds the control-flow graph of a memory image that can use the opcodes added to
+// `instructions`. Added opcodes can jump anywhere, so they end their block like a
+// conditional jump to a computed target.
+pub fn control_flow_graph_with(memory: &[i64], instructions: &InstructionSet) -> ControlFlowGraph {
+    // Find every reachable instruction, along with the addresses that start blocks
+    let mut ops = BTreeMap::new();
+    let mut leaders = BTreeSet::new();
+    let mut worklist = vec![0];
+    leaders.insert(0);
+    while let Some(mut address) = worklist.pop() {
+        while !ops.contains_key(&address) {
+            let op = match instruction_at(memory, address, instructions) {
+                Some(op) => op,
+                None => break,
+            };
+            ops.insert(address, op);
+            let next = next_address(address, &op);
+            if op.opcode == Opcode::Halt {
+                break;
+            }
+            if let Some((target, falls_through)) = jump(&op) {
+                if let Target::Address(target) = target {
+                    if leaders.insert(target) {
+                        worklist.push(target);
+                    }
+                }
+                if !falls_through {
+                    break;
+                }
+                leaders.insert(next);
+            }
+            address = next;
+        }
+    }
+
+    // Split the instructions into blocks at every leader and after every jump or halt
+    let mut blocks = Vec::new();
+    let mut remaining = ops.iter().peekable();
+    while let Some((&start, &first)) = remaining.next() {
+        let mut block = vec![start];
+        let (mut address, mut op) = (start, first);
+        while op.opcode != Opcode::Halt && jump(&op).is_none() {
+            let next = next_address(address, &op);
+            match remaining.peek() {
+                Some(&(&following, &following_op))
+                    if following == next && !leaders.contains(&next) =>
+                {
+                    remaining.next();
+                    block.push(next);
+                    address = next;
+                    op = following_op;
+                }
+                _ => break,
+            }
+        }
+        let next = next_address(address, &op);
+        let exit = match jump(&op) {
+            _ if op.opcode == Opcode::Halt => Exit::Halt,
+            Some((target, falls_through)) => Exit::Jump {
+                target,
+                next: if falls_through { Some(next) } else { None },
+            },
+            None => Exit::Next(next),
+        };
+        blocks.push(BasicBlock {
+            instructions: block,
+            exit,
+        });
+    }
+    ControlFlowGraph { blocks }
+}
+
+// Decodes an instruction the way the disassembler does, rejecting instructions that
+// can't execute or that run past the end of memory
+pub(super) fn instruction_at(
+    memory: &[i64],
+    address: usize,
+    instructions: &InstructionSet,
+) -> Option<Op> {
+    let op = decode(memory, address, instructions).ok()?;
+    if !is_well_formed(&op) || address + op.parameters().len() >= memory.len() {
+        return None;
+    }
+    Some(op)
+}
+
+fn next_address(address: usize, op: &Op) -> usize {
+    address + 1 + op.parameters().len()
+}
+
+// The target of a jump that can be taken, and whether it can also fall through
+fn jump(op: &Op) -> Option<(Target, bool)> {
+    if let Opcode::Extended(_) = op.opcode {
+        return Some((Target::Computed, true));
+    }
+    if op.opcode != Opcode::JumpIfTrue && op.opcode != Opcode::JumpIfFalse {
+        return None;
+    }
+    let condition = op.parameters[0];
+    let falls_through = match condition.mode {
+        ParameterMode::Immediate => {
+            if (condition.value != 0) != (op.opcode == Opcode::JumpIfTrue) {
+                // Never taken, so it's no different from any other instruction
+                return None;
+            }
+            false
+        }
+        _ => true,
+    };
+    let target = op.parameters[1];
+    let target = match target.mode {
+        ParameterMode::Immediate if target.value < 0 => Target::Negative(target.value),
+        ParameterMode::Immediate => Target::Address(target.value as usize),
+        _ => Target::Computed,
+    };
+    Some((target, falls_through))
+}
+
+impl ControlFlowGraph {
+    // Blocks in address order
+    pub fn blocks(&self) -> &[BasicBlock] {
+        &self.blocks
+    }
+
+    pub fn block_at(&self, start: usize) -> Option<&BasicBlock> {
+        self.blocks
+            .binary_search_by_key(&start, BasicBlock::start)
+            .ok()
+            .map(|index| &self.blocks[index])
+    }
+
+    // Renders the graph in Graphviz DOT format, labelling each block with its
+    // disassembly. Computed jumps lead to a `?` node, and jumps or fall-throughs into
+    // memory that isn't a valid instruction are drawn in red.
+    pub fn to_dot(&self, memory: &[i64]) -> String {
+        self.to_dot_with(memory, &InstructionSet::default())
+    }
+
+    // Renders the graph of a program that uses the opcodes added to `instructions`
+    pub fn to_dot_with(&self, memory: &[i64], instructions: &InstructionSet) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph intcode {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+        let mut invalid = BTreeSet::new();
+        let mut next_node = |address: usize| {
+            if self.block_at(address).is_some() {
+                format!("b{}", address)
+            } else {
+                invalid.insert(address);
+                format!("x{}", address)
+            }
+        };
+        for block in &self.blocks {
+            let start = block.start();
+            let count = block.instructions.len();
+            let listing = disassemble_window(memory, 0, start, count, instructions);
+            let label = listing
+                .entries()
+                .iter()
+                .map(|entry| format!("{}\\l", listing.format_entry(entry)))
+                .collect::<String>();
+            writeln!(dot, "    b{} [label=\"{}\"];", start, label).unwrap();
+
+            let mut edges = Vec::new();
+            match block.exit {
+                Exit::Next(next) => edges.push((next_node(next), "")),
+                Exit::Halt => {}
+                Exit::Jump { target, next } => {
+                    let taken = if next.is_some() { "taken" } else { "" };
+                    let node = match target {
+                        Target::Address(address) => next_node(address),
+                        Target::Computed => {
+                            writeln!(dot, "    c{} [label=\"?\", shape=circle];", start).unwrap();
+                            format!("c{}", start)
+                        }
+                        Target::Negative(value) => {
+                            writeln!(
+                                dot,
+                                "    n{} [label=\"{}\", shape=circle, color=red];",
+                                start, value
+                            )
+                            .unwrap();
+                            format!("n{}", start)
+                        }
+                    };
+                    edges.push((node, taken));
+                    if let Some(next) = next {
+                        edges.push((next_node(next), "not taken"));
+                    }
+                }
+            }
+            for (node, label) in edges {
+                if label.is_empty() {
+                    writeln!(dot, "    b{} -> {};", start, node).unwrap();
+                } else {
+                    writeln!(dot, "    b{} -> {} [label=\"{}\"];", start, node, label).unwrap();
+                }
+            }
+        }
+        for address in invalid {
+            writeln!(
+                dot,
+                "    x{} [label=\"{:04}: invalid\", color=red];",
+                address, address
+            )
+            .unwrap();
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+impl BasicBlock {
+    pub fn start(&self) -> usize {
+        self.instructions[0]
+    }
+
+    // The addresses execution can continue at after this block, not counting computed
+    // or negative jump targets
+    pub fn successors(&self) -> Vec<usize> {
+        match self.exit {
+            Exit::Next(next) => vec![next],
+            Exit::Jump { target, next } => {
+                let target = match target {
+                    Target::Address(address) => Some(address),
+                    _ => None,
+                };
+                target.into_iter().chain(next).collect()
+            }
+            Exit::Halt => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Counts down from its input, then jumps to a computed address if it's nonzero
+    fn program() -> Vec<i64> {
+        let source = "
+                    IN -> [counter]
+            loop:   OUT [counter]
+                    ADD [counter], #-1 -> [counter]
+                    JNZ [counter], #loop
+                    JZ #0, #done
+                    HALT
+            done:   JNZ [counter], [exit]
+                    HALT
+            counter: .data 0
+            exit:    .data 0
+        ";
+        assemble(source).unwrap()
+    }
+
+    #[test]
+    pub fn test_blocks() {
+        let graph = control_flow_graph(&program());
+        let blocks = graph
+            .blocks()
+            .iter()
+            .map(|block| (block.instructions.clone(), block.exit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (vec![0], Exit::Next(2)),
+                (
+                    vec![2, 4, 8],
+                    Exit::Jump {
+                        target: Target::Address(2),
+                        next: Some(11)
+                    }
+                ),
+                (
+                    vec![11],
+                    Exit::Jump {
+                        target: Target::Address(15),
+                        next: None
+                    }
+                ),
+                (
+                    vec![15],
+                    Exit::Jump {
+                        target: Target::Computed,
+                        next: Some(18)
+                    }
+                ),
+                (vec![18], Exit::Halt),
+            ],
+            blocks
+        );
+        assert_eq!(vec![2, 11], graph.block_at(2).unwrap().successors());
+        assert_eq!(vec![18], graph.block_at(15).unwrap().successors());
+        assert_eq!(None, graph.block_at(4));
+        assert_eq!(None, graph.block_at(14));
+    }
+
+    #[test]
+    pub fn test_unreachable_and_invalid() {
+        // The jump never falls through, so the ADD after it is never reached. The
+        // conditional jump at 5 can fall through into data.
+        let memory = vec![1105, 1, 5, 1, 0, 1005, 0, 0, 42];
+        let graph = control_flow_graph(&memory);
+        assert_eq!(
+            vec![0, 5],
+            graph
+                .blocks()
+                .iter()
+                .map(BasicBlock::start)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Exit::Jump {
+                target: Target::Address(0),
+                next: Some(8)
+            },
+            graph.blocks()[1].exit
+        );
+        assert_eq!(None, graph.block_at(8));
+    }
+
+    #[test]
+    pub fn test_dot() {
+        let memory = vec![3, 9, 1005, 9, 0, 106, 0, 10, 99, 0, 0];
+        let dot = control_flow_graph(&memory).to_dot(&memory);
+        assert_eq!(
+            r#"digraph intcode {
+    node [shape=box, fontname="monospace"];
+    b0 [label="0000: IN -> [9]\l0002: JNZ [9], #0\l"];
+    b0 -> b0 [label="taken"];
+    b0 -> b5 [label="not taken"];
+    b5 [label="0005: JZ #0, [10]\l"];
+    c5 [label="?", shape=circle];
+    b5 -> c5;
+}
+"#,
+            dot
+        );
+    }
+}
@@ -0,0 +1,704 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+
+use super::{Input, Intcode, IoBus, Output, State, TraceEvent, VmError};
+
+// How many instructions can be stepped back over unless told otherwise
+const DEFAULT_HISTORY_LIMIT: usize = 100_000;
+
+// The most words or instructions the REPL shows at once
+const MAX_LISTING: usize = 1024;
+
+pub struct Debugger {
+    program: Intcode,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeMap<usize, Watch>,
+    // What each executed instruction changed, most recent last
+    history: VecDeque<Undo>,
+    history_limit: usize,
+}
+
+// Everything needed to undo one instruction
+#[derive(Clone, Debug)]
+struct Undo {
+    ip: usize,
+    relative_base: i64,
+    state: State,
+    // One past the highest address in memory, which writes past the end can raise
+    memory_len: usize,
+    // The addresses written and the values they held before, in the order written
+    writes: Vec<(usize, i64)>,
+    inputs: Vec<i64>,
+    outputs: Vec<i64>,
+}
+
+// What stepping backwards undid
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Rewind {
+    pub steps: usize,
+    // Inputs the program read during the undone instructions, in the order it read them.
+    // The program reads them again when it re-executes those instructions, so they
+    // usually need to be sent again.
+    pub inputs: Vec<i64>,
+    // Outputs the undone instructions produced, in order
+    pub outputs: Vec<i64>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Watch {
+    Read,
+    Write,
+    Access,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryAccess {
+    Read,
+    Write,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum StopReason {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint {
+        address: usize,
+        access: MemoryAccess,
+        ip: usize,
+    },
+    WaitingForInput,
+    Halted,
+}
+
+impl Debugger {
+    pub fn new(program: Intcode) -> Self {
+        Self {
+            program,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            history: VecDeque::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+        }
+    }
+
+    // Keeps enough history to step back over the last `steps` instructions
+    pub fn with_history_limit(mut self, steps: usize) -> Self {
+        self.history_limit = steps;
+        while self.history.len() > steps {
+            self.history.pop_front();
+        }
+        self
+    }
+
+    // How many instructions can currently be stepped back over
+    pub fn history_len(&self) -> usize {
+        self.history.len()
+    }
+
+    pub fn program(&self) -> &Intcode {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Intcode {
+        &mut self.program
+    }
+
+    pub fn into_program(self) -> Intcode {
+        self.program
+    }
+
+    pub fn ip(&self) -> usize {
+        self.program.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.program.relative_base
+    }
+
+    // Reads a range of memory, treating memory that hasn't been touched yet as zero
+    pub fn memory(&self, range: Range<usize>) -> Vec<i64> {
+        self.program.memory_range(range)
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, address: usize, watch: Watch) {
+        self.watchpoints.insert(address, watch);
+    }
+
+    pub fn remove_watchpoint(&mut self, address: usize) -> bool {
+        self.watchpoints.remove(&address).is_some()
+    }
+
+    // Executes a single instruction
+    pub fn step<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<StopReason, VmError> {
+        Ok(self
+            .step_once(&mut input, &mut output)?
+            .unwrap_or(StopReason::Stepped))
+    }
+
+    // Runs until a breakpoint or watchpoint is hit, or the program halts or needs input.
+    // A breakpoint on the current instruction doesn't stop execution, so resuming after
+    // hitting a breakpoint makes progress.
+    pub fn resume<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<StopReason, VmError> {
+        if let Some(stop) = self.step_once(&mut input, &mut output)? {
+            return Ok(stop);
+        }
+        loop {
+            if self.breakpoints.contains(&self.program.ip) {
+                return Ok(StopReason::Breakpoint(self.program.ip));
+            }
+            if let Some(stop) = self.step_once(&mut input, &mut output)? {
+                return Ok(stop);
+            }
+        }
+    }
+
+    // Undoes the last `steps` instructions, or as many as the history allows
+    pub fn rewind(&mut self, steps: usize) -> Rewind {
+        let mut rewind = Rewind::default();
+        while rewind.steps < steps {
+            match self.history.pop_back() {
+                Some(undo) => self.undo(undo, &mut rewind),
+                None => break,
+            }
+        }
+        rewind.finish()
+    }
+
+    // Undoes instructions up to and including the most recent one that read input or
+    // produced output, leaving the program about to execute it again. Rewinds as far
+    // as the history allows if there was no input or output.
+    pub fn rewind_to_io(&mut self) -> Rewind {
+        let mut rewind = Rewind::default();
+        while let Some(undo) = self.history.pop_back() {
+            let is_io = !undo.inputs.is_empty() || !undo.outputs.is_empty();
+            self.undo(undo, &mut rewind);
+            if is_io {
+                break;
+            }
+        }
+        rewind.finish()
+    }
+
+    fn record(&mut self, before: (usize, i64, State, usize), event: &TraceEvent) {
+        let (ip, relative_base, state, memory_len) = before;
+        if self.history_limit == 0 {
+            return;
+        }
+        if self.history.len() == self.history_limit {
+            self.history.pop_front();
+        }
+        self.history.push_back(Undo {
+            ip,
+            relative_base,
+            state,
+            memory_len,
+            writes: event
+                .writes
+                .iter()
+                .map(|write| (write.address, write.old_value))
+                .collect(),
+            inputs: event.inputs.clone(),
+            outputs: event.outputs.clone(),
+        });
+    }
+
+    fn undo(&mut self, undo: Undo, rewind: &mut Rewind) {
+        // Undoing the writes in reverse restores the oldest value of a word written twice
+        for &(address, old_value) in undo.writes.iter().rev() {
+            // The page was already written to, so putting the old value back can't fail
+            let restored = self.program.memory.write(address, old_value);
+            debug_assert!(restored);
+        }
+        self.program.ip = undo.ip;
+        self.program.relative_base = undo.relative_base;
+        self.program.state = undo.state;
+        self.program.memory.set_len(undo.memory_len);
+        rewind.steps += 1;
+        rewind.inputs.extend(undo.inputs);
+        rewind.outputs.extend(undo.outputs);
+    }
+
+    fn step_once<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Option<StopReason>, VmError> {
+        let mut executed = None;
+        let before = (
+            self.program.ip,
+            self.program.relative_base,
+            self.program.state,
+            self.program.memory.len(),
+        );
+        let running = self
+            .program
+            .step_traced(input, output, &mut |event: &TraceEvent| {
+                executed = Some(event.clone())
+            })?;
+        if let Some(event) = &executed {
+            self.record(before, event);
+        }
+        if !running {
+            return Ok(Some(if self.program.is_halted() {
+                StopReason::Halted
+            } else {
+                StopReason::WaitingForInput
+            }));
+        }
+        let event = executed.expect("a running program traces every instruction it executes");
+        let reads = event
+            .operands
+            .iter()
+            .filter_map(|operand| operand.address)
+            .map(|address| (address, MemoryAccess::Read));
+        let writes = event
+            .writes
+            .iter()
+            .map(|write| (write.address, MemoryAccess::Write));
+        Ok(reads
+            .chain(writes)
+            .find(|&(address, access)| match self.watchpoints.get(&address) {
+                Some(Watch::Access) => true,
+                Some(Watch::Read) => access == MemoryAccess::Read,
+                Some(Watch::Write) => access == MemoryAccess::Write,
+                None => false,
+            })
+            .map(|(address, access)| StopReason::Watchpoint {
+                address,
+                access,
+                ip: event.ip,
+            }))
+    }
+
+    // A small line-oriented front end, reading commands from `reader` and writing
+    // results to `writer`. Type `help` for a list of commands.
+    pub fn repl<R: BufRead, W: Write>(&mut self, reader: R, mut writer: W) -> io::Result<()> {
+        let mut input = IoBus::default();
+        let mut output = IoBus::default();
+        write!(writer, "(icdb) ")?;
+        writer.flush()?;
+        for line in reader.lines() {
+            let line = line?;
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            // Argument `i`, or `default` if it was left out. `None` means it was invalid or
+            // required, in which case the command prints its usage.
+            let arg = |i: usize, default: Option<usize>| match words.get(i + 1) {
+                Some(word) => word.parse::<usize>().ok(),
+                None => default,
+            };
+            let result = match words.first().copied().unwrap_or("") {
+                "" => Ok(None),
+                "help" | "h" => {
+                    writeln!(writer, "{}", REPL_HELP)?;
+                    Ok(None)
+                }
+                "quit" | "q" => return Ok(()),
+                "step" | "s" => match arg(0, Some(1)) {
+                    Some(steps) => {
+                        let mut stop = Ok(StopReason::Stepped);
+                        for _ in 0..steps {
+                            stop = self.step(&mut input, &mut output);
+                            if stop != Ok(StopReason::Stepped) {
+                                break;
+                            }
+                        }
+                        stop.map(Some)
+                    }
+                    None => usage(&mut writer, "step [n]")?,
+                },
+                "continue" | "c" => self.resume(&mut input, &mut output).map(Some),
+                "back" | "bs" | "bio" => {
+                    let rewind = if words[0] == "bio" {
+                        Some(self.rewind_to_io())
+                    } else {
+                        arg(0, Some(1)).map(|steps| self.rewind(steps))
+                    };
+                    match rewind {
+                        Some(rewind) => {
+                            // The program reads the undone inputs again when it gets back
+                            // to them
+                            for &value in rewind.inputs.iter().rev() {
+                                input.values.push_front(value);
+                            }
+                            writeln!(writer, "rewound {} steps (ip={})", rewind.steps, self.ip())?;
+                            Ok(None)
+                        }
+                        None => usage(&mut writer, "back [n]")?,
+                    }
+                }
+                "break" | "b" => match arg(0, Some(self.ip())) {
+                    Some(address) => {
+                        self.add_breakpoint(address);
+                        Ok(None)
+                    }
+                    None => usage(&mut writer, "break [addr]")?,
+                },
+                "delete" | "d" => match arg(0, None) {
+                    Some(address) => {
+                        if !self.remove_breakpoint(address) && !self.remove_watchpoint(address) {
+                            writeln!(writer, "nothing set at {}", address)?;
+                        }
+                        Ok(None)
+                    }
+                    None => usage(&mut writer, "delete addr")?,
+                },
+                "watch" | "w" => {
+                    let watch = match words.get(2).copied() {
+                        Some("r") => Some(Watch::Read),
+                        Some("w") => Some(Watch::Write),
+                        Some("rw") | None => Some(Watch::Access),
+                        Some(_) => None,
+                    };
+                    match (words.get(1).and_then(|w| w.parse().ok()), watch) {
+                        (Some(address), Some(watch)) => self.add_watchpoint(address, watch),
+                        _ => writeln!(writer, "usage: watch addr [r|w|rw]")?,
+                    }
+                    Ok(None)
+                }
+                "input" | "i" => {
+                    for word in &words[1..] {
+                        match word.parse() {
+                            Ok(value) => input.write(value),
+                            Err(_) => writeln!(writer, "invalid input value `{}`", word)?,
+                        }
+                    }
+                    Ok(None)
+                }
+                "regs" | "r" => {
+                    writeln!(
+                        writer,
+                        "ip={} rb={} state={:?}",
+                        self.ip(),
+                        self.relative_base(),
+                        self.program.state
+                    )?;
+                    Ok(None)
+                }
+                "mem" | "x" => match (arg(0, None), arg(1, Some(1))) {
+                    (Some(start), Some(count)) if start.checked_add(count).is_some() => {
+                        let values = self.memory(start..start + count.min(MAX_LISTING));
+                        for (i, chunk) in values.chunks(8).enumerate() {
+                            let chunk = chunk.iter().map(i64::to_string).collect::<Vec<_>>();
+                            writeln!(writer, "{:04}: {}", start + i * 8, chunk.join(" "))?;
+                        }
+                        Ok(None)
+                    }
+                    _ => usage(&mut writer, "mem addr [n]")?,
+                },
+                "list" | "l" => match (arg(0, Some(self.ip())), arg(1, Some(5))) {
+                    (Some(start), Some(count)) => {
+                        let listing = self.program.disassemble(start, count.min(MAX_LISTING));
+                        write!(writer, "{}", listing)?;
+                        Ok(None)
+                    }
+                    _ => usage(&mut writer, "list [addr] [n]")?,
+                },
+                command => {
+                    writeln!(writer, "unknown command `{}`, try `help`", command)?;
+                    Ok(None)
+                }
+            };
+            while let Some(value) = output.read() {
+                writeln!(writer, "output: {}", value)?;
+            }
+            match result {
+                Ok(Some(stop)) => writeln!(writer, "{:?} (ip={})", stop, self.ip())?,
+                Ok(None) => {}
+                Err(e) => writeln!(writer, "error: {}", e)?,
+            }
+            write!(writer, "(icdb) ")?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
+
+// Tells the user how to call a command whose arguments were missing or invalid
+fn usage<W: Write>(writer: &mut W, usage: &str) -> io::Result<Result<Option<StopReason>, VmError>> {
+    writeln!(writer, "usage: {}", usage)?;
+    Ok(Ok(None))
+}
+
+impl Rewind {
+    // Instructions are undone newest first, so put what they did back in program order
+    fn finish(mut self) -> Self {
+        self.inputs.reverse();
+        self.outputs.reverse();
+        self
+    }
+}
+
+const REPL_HELP: &str = "commands:
+  s, step [n]             execute n instructions (default 1)
+  c, continue             run until a breakpoint, watchpoint, halt or input is needed
+  bs, back [n]            step back n instructions (default 1)
+  bio                     step back to the last instruction that did input or output
+  b, break [addr]         set a breakpoint (default: current ip)
+  w, watch addr [r|w|rw]  set a watchpoint on memory reads, writes or both
+  d, delete addr          remove a breakpoint or watchpoint
+  i, input v...           queue input values for the program
+  r, regs                 show ip, relative base and state
+  x, mem addr [n]         show n words of memory, up to 1024
+  l, list [addr] [n]      disassemble n instructions, up to 1024 (default: 5 at ip)
+  q, quit";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    fn countdown() -> Intcode {
+        let source = "
+                    IN -> [counter]
+            loop:   OUT [counter]
+                    ADD [counter], #-1 -> [counter]
+                    JNZ [counter], #loop
+                    HALT
+            counter: .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_breakpoints() {
+        let mut debugger = Debugger::new(countdown());
+        let mut input = IoBus::default();
+        let mut output = IoBus::default();
+        input.write(3);
+        debugger.add_breakpoint(4);
+        assert_eq!(
+            Ok(StopReason::Breakpoint(4)),
+            debugger.resume(&mut input, &mut output)
+        );
+        assert_eq!(Some(3), output.read());
+        assert_eq!(
+            Ok(StopReason::Breakpoint(4)),
+            debugger.resume(&mut input, &mut output)
+        );
+        assert_eq!(Some(2), output.read());
+
+        assert_eq!(
+            Ok(StopReason::Stepped),
+            debugger.step(&mut input, &mut output)
+        );
+        assert_eq!(8, debugger.ip());
+        assert_eq!(vec![1], debugger.memory(12..13));
+
+        debugger.remove_breakpoint(4);
+        assert_eq!(
+            Ok(StopReason::Halted),
+            debugger.resume(&mut input, &mut output)
+        );
+        assert_eq!(Some(1), output.read());
+    }
+
+    #[test]
+    pub fn test_watchpoints() {
+        let mut debugger = Debugger::new(countdown());
+        let mut input = IoBus::default();
+        let mut output = IoBus::default();
+        assert_eq!(
+            Ok(StopReason::WaitingForInput),
+            debugger.resume(&mut input, &mut output)
+        );
+
+        input.write(2);
+        debugger.add_watchpoint(12, Watch::Write);
+        let stop = debugger.resume(&mut input, &mut output);
+        assert_eq!(
+            Ok(StopReason::Watchpoint {
+                address: 12,
+                access: MemoryAccess::Write,
+                ip: 0
+            }),
+            stop
+        );
+        assert_eq!(vec![2], debugger.memory(12..13));
+        assert_eq!(None, output.read());
+
+        debugger.remove_watchpoint(12);
+        debugger.add_watchpoint(12, Watch::Read);
+        let stop = debugger.resume(&mut input, &mut output);
+        assert_eq!(
+            Ok(StopReason::Watchpoint {
+                address: 12,
+                access: MemoryAccess::Read,
+                ip: 2
+            }),
+            stop
+        );
+        assert_eq!(Some(2), output.read());
+    }
+
+    #[test]
+    pub fn test_rewind() {
+        let mut debugger = Debugger::new(countdown());
+        let mut input = IoBus::default();
+        let mut output = IoBus::default();
+        input.write(2);
+        assert_eq!(
+            Ok(StopReason::Halted),
+            debugger.resume(&mut input, &mut output)
+        );
+        assert_eq!(8, debugger.history_len());
+
+        assert_eq!(
+            Rewind {
+                steps: 1,
+                inputs: vec![],
+                outputs: vec![]
+            },
+            debugger.rewind(1)
+        );
+        assert_eq!(11, debugger.ip());
+        assert!(!debugger.program().is_halted());
+
+        assert_eq!(
+            Rewind {
+                steps: 3,
+                inputs: vec![],
+                outputs: vec![1]
+            },
+            debugger.rewind_to_io()
+        );
+        assert_eq!(2, debugger.ip());
+        assert_eq!(vec![1], debugger.memory(12..13));
+
+        assert_eq!(
+            Rewind {
+                steps: 4,
+                inputs: vec![2],
+                outputs: vec![2]
+            },
+            debugger.rewind(10)
+        );
+        assert_eq!(0, debugger.ip());
+        assert_eq!(vec![0], debugger.memory(12..13));
+        assert_eq!(0, debugger.history_len());
+
+        // Running forward again repeats the same outputs
+        let mut output = IoBus::default();
+        input.write(2);
+        assert_eq!(
+            Ok(StopReason::Halted),
+            debugger.resume(&mut input, &mut output)
+        );
+        assert_eq!((Some(2), Some(1)), (output.read(), output.read()));
+
+        let mut debugger = Debugger::new(countdown()).with_history_limit(2);
+        input.write(2);
+        debugger.resume(&mut input, &mut output).unwrap();
+        assert_eq!(2, debugger.rewind(5).steps);
+        assert_eq!(8, debugger.ip());
+
+        // Rewinding a write past the end of memory shrinks memory back
+        let program = "1101,1,2,20,99".parse::<Intcode>().unwrap();
+        let mut debugger = Debugger::new(program.clone());
+        debugger.step(|| 0, |_| {}).unwrap();
+        assert_eq!(21, debugger.program().memory().len());
+        debugger.rewind(1);
+        assert_eq!(program.memory(), debugger.program().memory());
+    }
+
+    #[test]
+    pub fn test_repl() {
+        let mut debugger = Debugger::new(countdown());
+        let commands = "b 4\ni 2\nc\nr\nx 12\nc\nl 8 2\nd 4\nc\nq\n";
+        let mut transcript = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut transcript).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert_eq!(
+            "(icdb) (icdb) (icdb) output: 2
+Breakpoint(4) (ip=4)
+(icdb) ip=4 rb=0 state=Running
+(icdb) 0012: 2
+(icdb) output: 1
+Breakpoint(4) (ip=4)
+(icdb) 0008: JNZ [12], #2
+0011: HALT
+(icdb) (icdb) Halted (ip=11)
+(icdb) ",
+            transcript
+        );
+    }
+
+    #[test]
+    pub fn test_repl_rewind() {
+        let mut debugger = Debugger::new(countdown());
+        let commands = "i 2\nc\nbio\nr\nbs 2\nx 12\nbs 5\nc\n";
+        let mut transcript = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut transcript).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert_eq!(
+            "(icdb) (icdb) output: 2
+output: 1
+Halted (ip=11)
+(icdb) rewound 4 steps (ip=2)
+(icdb) ip=2 rb=0 state=Running
+(icdb) rewound 2 steps (ip=4)
+(icdb) 0012: 2
+(icdb) rewound 2 steps (ip=0)
+(icdb) output: 2
+output: 1
+Halted (ip=11)
+(icdb) ",
+            transcript
+        );
+    }
+
+    #[test]
+    pub fn test_repl_usage() {
+        let mut debugger = Debugger::new(countdown());
+        let commands = "x 0 -1\nd\nx\nb -3\ns x\nbs -1\nl 0 many\nx 5 18446744073709551615\n";
+        let mut transcript = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut transcript).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert_eq!(
+            "(icdb) usage: mem addr [n]
+(icdb) usage: delete addr
+(icdb) usage: mem addr [n]
+(icdb) usage: break [addr]
+(icdb) usage: step [n]
+(icdb) usage: back [n]
+(icdb) usage: list [addr] [n]
+(icdb) usage: mem addr [n]
+(icdb) ",
+            transcript
+        );
+        assert_eq!(0, debugger.ip());
+    }
+
+    #[test]
+    pub fn test_repl_limits() {
+        let mut debugger = Debugger::new(countdown());
+        let commands = "x 1000000 1000000000000
+l 0 1000000000000
+";
+        let mut transcript = Vec::new();
+        debugger.repl(commands.as_bytes(), &mut transcript).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        let lines = transcript.lines().collect::<Vec<_>>();
+        // MAX_LISTING words, then the whole program and the final prompt
+        assert_eq!(MAX_LISTING / 8 + 7, lines.len());
+        assert_eq!("(icdb) 1000000: 0 0 0 0 0 0 0 0", lines[0]);
+        assert_eq!("1001016: 0 0 0 0 0 0 0 0", lines[MAX_LISTING / 8 - 1]);
+        assert_eq!("0012: DATA 0", lines[lines.len() - 2]);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeSet;
+use std::fmt::{self, Display, Formatter};
+
+use super::{InstructionSet, Intcode, Op, Opcode, Parameter, ParameterMode, MAX_PARAMETERS};
+
+#[derive(Clone, Debug)]
+pub struct Listing {
+    entries: Vec<Entry>,
+    labels: BTreeSet<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+    address: usize,
+    contents: Contents,
+}
+
+#[derive(Clone, Debug)]
+enum Contents {
+    Instruction(Op),
+    Data(i64),
+}
+
+// Disassembles a memory image from start to finish. Words that don't decode into a valid
+// instruction (including ones that would write to an immediate parameter, or whose
+// parameters run past the end of the image) are listed as data.
+pub fn disassemble(memory: &[i64]) -> Listing {
+    disassemble_with(memory, &InstructionSet::default())
+}
+
+// Disassembles a memory image that can use the opcodes added to `instructions`
+pub fn disassemble_with(memory: &[i64], instructions: &InstructionSet) -> Listing {
+    disassemble_window(memory, 0, 0, usize::MAX, instructions)
+}
+
+// Disassembles at most `count` entries, starting from an arbitrary address
+pub fn disassemble_from(memory: &[i64], start: usize, count: usize) -> Listing {
+    disassemble_window(memory, 0, start, count, &InstructionSet::default())
+}
+
+impl Intcode {
+    // Disassembles at most `count` entries of the program's memory as it is now, starting
+    // from an arbitrary address. Only the words those entries can cover are read, so
+    // this is safe on programs that wrote to far addresses of sparse memory.
+    pub fn disassemble(&self, start: usize, count: usize) -> Listing {
+        let end = count
+            .saturating_mul(1 + MAX_PARAMETERS)
+            .saturating_add(start)
+            .min(self.memory.len())
+            .max(start);
+        let words = self.memory_range(start..end);
+        disassemble_window(&words, start, start, count, &self.instructions)
+    }
+}
+
+// Disassembles `words`, which are the contents of memory from address `base` on
+pub(super) fn disassemble_window(
+    words: &[i64],
+    base: usize,
+    start: usize,
+    count: usize,
+    instructions: &InstructionSet,
+) -> Listing {
+    let end = base + words.len();
+    let word = |address: usize| words.get(address - base).copied().unwrap_or(0);
+    let mut entries = Vec::new();
+    let mut address = start;
+    while address < end && entries.len() < count {
+        let op = instructions
+            .decode(word(address), address)
+            .map(|header| header.with_parameters(|i| word(address + 1 + i)));
+        let contents = match op {
+            Ok(ref op) if !is_well_formed(op) => Contents::Data(word(address)),
+            Ok(ref op) if address + op.parameters().len() >= end => Contents::Data(word(address)),
+            Ok(op) => Contents::Instruction(op),
+            Err(_) => Contents::Data(word(address)),
+        };
+        let entry = Entry { address, contents };
+        address += entry.size();
+        entries.push(entry);
+    }
+    Listing {
+        entries,
+        labels: BTreeSet::new(),
+    }
+}
+
+pub(super) fn is_well_formed(op: &Op) -> bool {
+    match op.opcode.write_parameter() {
+        Some(i) => op.parameters[i].mode != ParameterMode::Immediate,
+        None => true,
+    }
+}
+
+impl Listing {
+    // Labels every entry that is the immediate target of a jump instruction. Targets in
+    // the middle of an entry or outside the listing are left as plain addresses, since
+    // there is no line to put their label on.
+    pub fn with_labels(mut self) -> Self {
+        self.labels = self
+            .entries
+            .iter()
+            .filter_map(Entry::jump_target)
+            .filter(|&target| target >= 0)
+            .map(|target| target as usize)
+            .filter(|&target| {
+                self.entries
+                    .binary_search_by_key(&target, |e| e.address)
+                    .is_ok()
+            })
+            .collect();
+        self
+    }
+
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    // Finds the entry covering a particular address
+    pub fn entry_at(&self, address: usize) -> Option<&Entry> {
+        let index = match self.entries.binary_search_by_key(&address, |e| e.address) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        self.entries
+            .get(index)
+            .filter(|e| address < e.address + e.size())
+    }
+
+    pub fn format_entry(&self, entry: &Entry) -> String {
+        let mut s = format!("{:04}: ", entry.address);
+        match &entry.contents {
+            Contents::Data(value) => s.push_str(&format!("DATA {}", value)),
+            Contents::Instruction(op) => {
+                s.push_str(op.opcode.mnemonic());
+                let destination = op.opcode.write_parameter();
+                let operands = op
+                    .parameters()
+                    .iter()
+                    .enumerate()
+                    .filter(|&(i, _)| Some(i) != destination)
+                    .map(|(i, &parameter)| {
+                        let is_jump_target = i == 1 && entry.jump_target().is_some();
+                        self.format_operand(parameter, is_jump_target)
+                    })
+                    .collect::<Vec<_>>();
+                if !operands.is_empty() {
+                    s.push(' ');
+                    s.push_str(&operands.join(", "));
+                }
+                if let Some(i) = destination {
+                    s.push_str(" -> ");
+                    s.push_str(&self.format_operand(op.parameters[i], false));
+                }
+            }
+        }
+        s
+    }
+
+    fn format_operand(&self, parameter: Parameter, is_jump_target: bool) -> String {
+        match parameter.mode {
+            ParameterMode::Immediate
+                if is_jump_target && self.labels.contains(&(parameter.value as usize)) =>
+            {
+                format!("#{}", label(parameter.value as usize))
+            }
+            ParameterMode::Immediate => format!("#{}", parameter.value),
+            ParameterMode::Position => format!("[{}]", parameter.value),
+            ParameterMode::Relative if parameter.value < 0 => {
+                format!("[rb-{}]", -parameter.value)
+            }
+            ParameterMode::Relative => format!("[rb+{}]", parameter.value),
+        }
+    }
+}
+
+fn label(address: usize) -> String {
+    format!("L{:04}", address)
+}
+
+impl Entry {
+    pub fn address(&self) -> usize {
+        self.address
+    }
+
+    // The number of memory words this entry covers
+    pub fn size(&self) -> usize {
+        match &self.contents {
+            Contents::Instruction(op) => 1 + op.parameters().len(),
+            Contents::Data(_) => 1,
+        }
+    }
+
+    pub fn is_data(&self) -> bool {
+        match self.contents {
+            Contents::Data(_) => true,
+            Contents::Instruction(_) => false,
+        }
+    }
+
+    fn jump_target(&self) -> Option<i64> {
+        match &self.contents {
+            Contents::Instruction(op)
+                if (op.opcode == Opcode::JumpIfTrue || op.opcode == Opcode::JumpIfFalse)
+                    && op.parameters[1].mode == ParameterMode::Immediate =>
+            {
+                Some(op.parameters[1].value)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for entry in &self.entries {
+            if self.labels.contains(&entry.address) {
+                writeln!(f, "{}:", label(entry.address))?;
+            }
+            writeln!(f, "{}", self.format_entry(entry))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, MemoryModel, Profile};
+
+    #[test]
+    pub fn test_disassemble() {
+        let memory = vec![1, 9, 10, 3, 21101, 5, -2, 100, 99, 30, 40, 50];
+        assert_eq!(
+            "0000: ADD [9], [10] -> [3]
+0004: ADD #5, #-2 -> [rb+100]
+0008: HALT
+0009: DATA 30
+0010: DATA 40
+0011: DATA 50
+",
+            disassemble(&memory).to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_labels() {
+        let memory = vec![3, 10, 1005, 10, 7, 4, 10, 1106, 0, 0, 0];
+        assert_eq!(
+            "L0000:
+0000: IN -> [10]
+0002: JNZ [10], #L0007
+0005: OUT [10]
+L0007:
+0007: JZ #0, #L0000
+0010: DATA 0
+",
+            disassemble(&memory).with_labels().to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_disassemble_program() {
+        // Writes to 10^12, which a listing of the whole of memory couldn't cope with
+        let mut program = "21101,0,42,1000000000000,99"
+            .parse::<Intcode>()
+            .unwrap()
+            .with_memory_model(MemoryModel::Sparse);
+        let mut profile = Profile::new();
+        program.execute_traced(|| 0, |_| {}, &mut profile).unwrap();
+        assert_eq!(
+            "0000: ADD #0, #42 -> [rb+1000000000000]\n0004: HALT\n",
+            program.disassemble(0, 2).to_string()
+        );
+        assert_eq!("0004: HALT\n", program.disassemble(4, 1).to_string());
+        assert!(profile.report(&program, 5).contains("0004: HALT"));
+    }
+
+    #[test]
+    pub fn test_labels_round_trip() {
+        // Jumps into the middle of an instruction, past the end, and to an instruction
+        let memory = vec![1105, 1, 4, 1106, 0, 100, 1105, 1, 3, 99];
+        let listing = disassemble(&memory).with_labels().to_string();
+        assert_eq!(
+            "0000: JNZ #1, #4
+L0003:
+0003: JZ #0, #100
+0006: JNZ #1, #L0003
+0009: HALT
+",
+            listing
+        );
+        assert_eq!(Ok(memory), assemble(&listing));
+    }
+}
@@ -0,0 +1,187 @@
+use super::{Budget, Intcode, IoBus, RunOutcome, State, Transcript, VmError};
+
+// Drives a program by queueing input for it and pulling its outputs one at a time, so
+// callers don't have to juggle buses or check why the program stopped
+pub struct Driver {
+    program: Intcode,
+    input: IoBus,
+    transcript: Option<Transcript>,
+}
+
+// Iterates over the outputs of a program until it halts or needs more input
+pub struct Outputs<'a> {
+    driver: &'a mut Driver,
+    failed: bool,
+}
+
+impl Driver {
+    pub fn new(program: Intcode) -> Self {
+        Self {
+            program,
+            input: IoBus::default(),
+            transcript: None,
+        }
+    }
+
+    // Records everything the program reads and outputs from now on, see `transcript`
+    pub fn with_recording(mut self) -> Self {
+        self.transcript.get_or_insert_with(Transcript::new);
+        self
+    }
+
+    pub fn transcript(&self) -> Option<&Transcript> {
+        self.transcript.as_ref()
+    }
+
+    pub fn program(&self) -> &Intcode {
+        &self.program
+    }
+
+    pub fn program_mut(&mut self) -> &mut Intcode {
+        &mut self.program
+    }
+
+    pub fn into_program(self) -> Intcode {
+        self.program
+    }
+
+    // Queues input for the program, which it reads when it next runs
+    pub fn send(&mut self, values: &[i64]) {
+        for &value in values {
+            self.input.write(value);
+        }
+    }
+
+    // Runs the program until it produces an output, returning `None` if it halts or
+    // needs more input than has been sent first
+    pub fn run_until_output(&mut self) -> Result<Option<i64>, VmError> {
+        let outcome = match &mut self.transcript {
+            Some(transcript) => self.program.run_traced_with_budget(
+                &mut self.input,
+                transcript,
+                &mut Budget::unlimited(),
+            )?,
+            None => self.program.run(&mut self.input)?,
+        };
+        match outcome {
+            RunOutcome::Output(value) => Ok(Some(value)),
+            _ => Ok(None),
+        }
+    }
+
+    // Collects the next `n` outputs. Fewer are returned if the program halts or needs
+    // more input first.
+    pub fn recv_n(&mut self, n: usize) -> Result<Vec<i64>, VmError> {
+        let mut values = Vec::with_capacity(n);
+        while values.len() < n {
+            match self.run_until_output()? {
+                Some(value) => values.push(value),
+                None => break,
+            }
+        }
+        Ok(values)
+    }
+
+    pub fn outputs(&mut self) -> Outputs<'_> {
+        Outputs {
+            driver: self,
+            failed: false,
+        }
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.program.is_halted()
+    }
+
+    // Whether the program is stuck waiting for input that hasn't been sent yet
+    pub fn needs_input(&self) -> bool {
+        matches!(self.program.state, State::WaitingForInput(_)) && self.input.values.is_empty()
+    }
+}
+
+impl Iterator for Outputs<'_> {
+    type Item = Result<i64, VmError>;
+
+    // Stops after the first error
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let next = self.driver.run_until_output().transpose();
+        self.failed = matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Outputs each input followed by its square, until it reads a zero
+    fn squares() -> Driver {
+        let source = "
+            loop:   IN -> [value]
+                    JZ [value], #end
+                    OUT [value]
+                    MUL [value], [value] -> [value]
+                    OUT [value]
+                    JZ #0, #loop
+            end:    HALT
+            value:  .data 0
+        ";
+        Driver::new(Intcode::new(assemble(source).unwrap()))
+    }
+
+    #[test]
+    pub fn test_send_and_receive() {
+        let mut driver = squares();
+        assert_eq!(Ok(None), driver.run_until_output());
+        assert!(driver.needs_input());
+
+        driver.send(&[3, 4]);
+        assert!(!driver.needs_input());
+        assert_eq!(Ok(Some(3)), driver.run_until_output());
+        assert_eq!(Ok(vec![9, 4, 16]), driver.recv_n(3));
+        assert_eq!(Ok(vec![]), driver.recv_n(2));
+        assert!(driver.needs_input());
+
+        driver.send(&[5, 0]);
+        assert_eq!(Ok(vec![5, 25]), driver.recv_n(3));
+        assert!(driver.is_halted());
+        assert!(!driver.needs_input());
+    }
+
+    #[test]
+    pub fn test_outputs() {
+        let mut driver = squares();
+        driver.send(&[1, 2]);
+        let outputs = driver.outputs().collect::<Result<Vec<_>, _>>();
+        assert_eq!(Ok(vec![1, 1, 2, 4]), outputs);
+
+        let mut driver = Driver::new("104,1,42".parse().unwrap());
+        let outputs = driver.outputs().collect::<Vec<_>>();
+        assert_eq!(
+            vec![Ok(1), Err(VmError::UnknownOpcode { opcode: 42, ip: 2 })],
+            outputs
+        );
+    }
+
+    #[test]
+    pub fn test_recording() {
+        let mut driver = squares().with_recording();
+        driver.send(&[3]);
+        assert_eq!(Ok(vec![3, 9]), driver.recv_n(2));
+        driver.send(&[0]);
+        assert_eq!(Ok(None), driver.run_until_output());
+
+        let transcript = driver.transcript().unwrap();
+        assert_eq!(vec![3, 0], transcript.inputs().collect::<Vec<_>>());
+        assert_eq!(vec![3, 9], transcript.outputs().collect::<Vec<_>>());
+        assert_eq!(
+            Ok(RunOutcome::Halted),
+            transcript.replay(&mut squares().into_program())
+        );
+        assert_eq!(None, squares().transcript());
+    }
+}
@@ -0,0 +1,581 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+
+use super::assembler::mode_digit;
+use super::{
+    Budget, Intcode, IoBus, LinearExpr, MemoryModel, Opcode, ParameterMode, RunOutcome,
+    SymbolicError, SymbolicIntcode, Transcript, VmError,
+};
+
+// Generated programs keep their variables in a data region just past the code
+const DATA_WORDS: usize = 16;
+const MAX_INSTRUCTIONS: usize = 32;
+// Far enough past the data to need memory pages of its own
+const FAR_ADDRESS: i64 = 5000;
+// Each addition at most doubles the largest value in memory, and each multiplication
+// (which is always by a small constant) at most triples it, so with few enough
+// instructions values stay well clear of overflowing
+const MAX_MULTIPLICATIONS: usize = 6;
+// Jumps only go forwards, so programs always stop well within this many steps
+const MAX_STEPS: u64 = 1000;
+// Jump targets that aren't immediate are kept in a table this far past the start of the
+// data, out of reach of every write: the relative base moves at most one word per
+// instruction, and relative writes are at most half the data past it
+const JUMP_TABLE_OFFSET: usize = DATA_WORDS + MAX_INSTRUCTIONS;
+
+// The case whose program `transpiled` was generated from
+#[cfg(test)]
+pub(super) const TRANSPILED_SEED: u64 = 109;
+
+// The instructions programs are made of, repeated to make some more likely than others
+const OPCODES: [Opcode; 13] = [
+    Opcode::Add,
+    Opcode::Add,
+    Opcode::Multiply,
+    Opcode::Input,
+    Opcode::Input,
+    Opcode::Output,
+    Opcode::Output,
+    Opcode::JumpIfTrue,
+    Opcode::JumpIfFalse,
+    Opcode::LessThan,
+    Opcode::Equals,
+    Opcode::RelativeBaseOffset,
+    Opcode::Halt,
+];
+
+// A random program and the inputs to run it with, which can all be recreated from the
+// seed. Programs only jump forwards, and only modify their own code by replacing one
+// instruction with another that takes the same parameters, so they always halt, run
+// out of input or fail within a few dozen steps.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FuzzCase {
+    pub seed: u64,
+    pub memory: Vec<i64>,
+    pub inputs: Vec<i64>,
+}
+
+// A way of running a program that didn't end the same as running it straight through
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FuzzFailure {
+    pub seed: u64,
+    pub check: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+// Everything observable about how a run ended
+#[derive(Clone, PartialEq, Eq, Debug)]
+struct Outcome {
+    result: Result<RunOutcome, VmError>,
+    outputs: Vec<i64>,
+    memory: Vec<i64>,
+    ip: usize,
+    relative_base: i64,
+}
+
+// The transpiler's output for one generated program, which every case is also run with.
+// Compiled instructions only check the opcode and parameter modes at their address
+// before running, so other programs run partly compiled and partly through the
+// generated interpreter. Regenerate it with `UPDATE_TRANSPILED=1 cargo test transpiler`.
+mod transpiled;
+
+// SplitMix64, which gives unrelated sequences for consecutive seeds
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number in `0..n`
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    // A number in `low..=high`
+    fn between(&mut self, low: i64, high: i64) -> i64 {
+        low + (self.next() % (high - low + 1) as u64) as i64
+    }
+
+    fn chance(&mut self, percent: u64) -> bool {
+        self.next() % 100 < percent
+    }
+
+    fn choose<T: Copy>(&mut self, items: &[T]) -> T {
+        items[self.below(items.len())]
+    }
+}
+
+impl FuzzCase {
+    pub fn generate(seed: u64) -> Self {
+        let mut rng = Rng(seed);
+
+        // Pick the instructions first, so that jumps know where later instructions start.
+        // The first instruction points the relative base at the data.
+        let count = 2 + rng.below(MAX_INSTRUCTIONS - 1);
+        let mut opcodes = vec![Opcode::RelativeBaseOffset];
+        for _ in 2..count {
+            let opcode = rng.choose(&OPCODES);
+            let multiplications = opcodes.iter().filter(|&&op| op == Opcode::Multiply);
+            if opcode == Opcode::Multiply && multiplications.count() == MAX_MULTIPLICATIONS {
+                opcodes.push(Opcode::Add);
+            } else {
+                opcodes.push(opcode);
+            }
+        }
+        opcodes.push(Opcode::Halt);
+        let mut starts = Vec::with_capacity(opcodes.len());
+        let mut code_words = 0;
+        for opcode in &opcodes {
+            starts.push(code_words);
+            code_words += 1 + opcode.parameter_count();
+        }
+        let data = code_words as i64;
+
+        let table = data + JUMP_TABLE_OFFSET as i64;
+        let mut jump_table = Vec::new();
+        // How far past the data the relative base can have moved so far
+        let mut spread = 0;
+        let mut instructions = Vec::with_capacity(opcodes.len());
+        let mut input_count = 0;
+        for (i, &opcode) in opcodes.iter().enumerate() {
+            let parameters = match opcode {
+                Opcode::RelativeBaseOffset if i == 0 => vec![(ParameterMode::Immediate, data)],
+                // The relative base only moves forwards, so relative addresses are never
+                // in the code
+                Opcode::RelativeBaseOffset => {
+                    let offset = rng.between(0, 1);
+                    spread += offset as usize;
+                    vec![(ParameterMode::Immediate, offset)]
+                }
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                    let target = starts[i + 1 + rng.below(opcodes.len() - i - 1)] as i64;
+                    let address = table + jump_table.len() as i64;
+                    let target = match rng.below(3) {
+                        0 => (ParameterMode::Immediate, target),
+                        1 => {
+                            jump_table.push(target);
+                            (ParameterMode::Position, address)
+                        }
+                        // Whichever jumps ran before, the relative base is somewhere in
+                        // the first `spread + 1` words of the data, so the target is
+                        // repeated that many times
+                        _ => {
+                            jump_table.resize(jump_table.len() + spread + 1, target);
+                            (ParameterMode::Relative, address - data)
+                        }
+                    };
+                    vec![source(&mut rng, data), target]
+                }
+                Opcode::Input => {
+                    input_count += 1;
+                    vec![destination(&mut rng, data)]
+                }
+                Opcode::Output => vec![source(&mut rng, data)],
+                Opcode::Multiply => vec![
+                    (ParameterMode::Immediate, rng.between(-3, 3)),
+                    source(&mut rng, data),
+                    destination(&mut rng, data),
+                ],
+                Opcode::Halt => Vec::new(),
+                _ => vec![
+                    source(&mut rng, data),
+                    source(&mut rng, data),
+                    destination(&mut rng, data),
+                ],
+            };
+            instructions.push((opcode, parameters));
+        }
+
+        // Turn some additions into patches that overwrite a later instruction with another
+        // one with the same parameters. Multiplications are never patched in, so that
+        // there's no more of them than planned.
+        for i in 0..instructions.len() {
+            if instructions[i].0 != Opcode::Add || !rng.chance(15) {
+                continue;
+            }
+            let patchable = (i + 1..instructions.len())
+                .filter(|&j| replacements(instructions[j].0).is_some())
+                .collect::<Vec<_>>();
+            if patchable.is_empty() {
+                continue;
+            }
+            let j = rng.choose(&patchable);
+            let (opcode, ref parameters) = instructions[j];
+            let replacement = rng.choose(replacements(opcode).unwrap());
+            let patch = header(replacement, parameters);
+            instructions[i].1 = vec![
+                (ParameterMode::Immediate, patch),
+                (ParameterMode::Immediate, 0),
+                (ParameterMode::Position, starts[j] as i64),
+            ];
+        }
+
+        let mut memory = Vec::with_capacity(code_words + DATA_WORDS);
+        for (opcode, parameters) in &instructions {
+            memory.push(header(*opcode, parameters));
+            memory.extend(parameters.iter().map(|&(_, value)| value));
+        }
+        memory.extend((0..DATA_WORDS).map(|_| rng.between(-20, 20)));
+        if !jump_table.is_empty() {
+            memory.resize(table as usize, 0);
+            memory.extend(jump_table);
+        }
+
+        // Sometimes leave the program short of input
+        if rng.chance(20) {
+            input_count = rng.below(input_count + 1);
+        }
+        let inputs = (0..input_count).map(|_| rng.between(-100, 100)).collect();
+        FuzzCase {
+            seed,
+            memory,
+            inputs,
+        }
+    }
+
+    // Runs the program straight through with the interpreter, then checks that every
+    // other way of running it ends the same way: with sparse memory, with a tracer, with
+    // symbolic execution, transpiled, interrupted partway through and cloned,
+    // snapshotted or switched to sparse memory, given one input at a time, and stopping
+    // after every output
+    pub fn check(&self) -> Result<(), FuzzFailure> {
+        let mut rng = Rng(!self.seed);
+        let (expected, steps) = self.reference();
+        let check = |check: &'static str, actual: Outcome| {
+            if actual == expected {
+                Ok(())
+            } else {
+                Err(FuzzFailure {
+                    seed: self.seed,
+                    check,
+                    expected: format!("{:?}", expected),
+                    actual: format!("{:?}", actual),
+                })
+            }
+        };
+
+        let program = self.program().with_memory_model(MemoryModel::Sparse);
+        check(
+            "sparse memory",
+            self.resume(program, self.bus(), Vec::new(), MAX_STEPS),
+        )?;
+
+        let mut program = self.program();
+        let mut transcript = Transcript::new();
+        let mut outputs = Vec::new();
+        let result = program.execute_traced_with_budget(
+            &mut self.bus(),
+            |value| outputs.push(value),
+            &mut transcript,
+            &mut Budget::steps(MAX_STEPS),
+        );
+        let inputs = transcript.inputs().collect::<Vec<_>>();
+        let recorded = transcript.outputs().collect::<Vec<_>>();
+        check("traced", outcome(&program, result, outputs))?;
+        if !self.inputs.starts_with(&inputs) || recorded != expected.outputs {
+            return Err(FuzzFailure {
+                seed: self.seed,
+                check: "transcript",
+                expected: format!("inputs {:?}, outputs {:?}", self.inputs, expected.outputs),
+                actual: format!("inputs {:?}, outputs {:?}", inputs, recorded),
+            });
+        }
+
+        check("symbolic", self.symbolic(&expected))?;
+        check("transpiled", self.transpiled())?;
+
+        // Interrupt the program after a random number of steps
+        let split = rng.below(steps as usize + 1) as u64;
+        let remaining = MAX_STEPS - split;
+        let mut program = self.program();
+        let mut bus = self.bus();
+        let mut outputs = Vec::new();
+        let result = program.execute_with_budget(
+            &mut bus,
+            |value| outputs.push(value),
+            &mut Budget::steps(split),
+        );
+        if result != Ok(RunOutcome::BudgetExhausted) {
+            return check("interrupted", outcome(&program, result, outputs));
+        }
+        let clone = program.clone();
+        let mut snapshot = Vec::new();
+        program.save_snapshot(&mut snapshot).unwrap();
+        let restored = Intcode::load_snapshot(&snapshot[..]).unwrap();
+        let sparse = program.clone().with_memory_model(MemoryModel::Sparse);
+        check(
+            "interrupted",
+            self.resume(program, bus.clone(), outputs.clone(), remaining),
+        )?;
+        // The clone shares memory pages with the program it came from, which has since
+        // written to them
+        check(
+            "cloned",
+            self.resume(clone, bus.clone(), outputs.clone(), remaining),
+        )?;
+        check(
+            "snapshot",
+            self.resume(restored, bus.clone(), outputs.clone(), remaining),
+        )?;
+        check(
+            "switched to sparse memory",
+            self.resume(sparse, bus, outputs, remaining),
+        )?;
+
+        check("one input at a time", self.one_input_at_a_time(&mut rng))?;
+        check("stopping after outputs", self.stopping_after_outputs())
+    }
+
+    fn program(&self) -> Intcode {
+        Intcode::new(self.memory.clone())
+    }
+
+    fn bus(&self) -> IoBus {
+        let mut bus = IoBus::default();
+        for &value in &self.inputs {
+            bus.write(value);
+        }
+        bus
+    }
+
+    // How the program ends when run straight through, and how many steps that takes
+    fn reference(&self) -> (Outcome, u64) {
+        let mut program = self.program();
+        let mut budget = Budget::steps(MAX_STEPS);
+        let mut outputs = Vec::new();
+        let result =
+            program.execute_with_budget(&mut self.bus(), |value| outputs.push(value), &mut budget);
+        (outcome(&program, result, outputs), budget.steps_taken())
+    }
+
+    // Runs the program to the end with the inputs and outputs from the first part of a
+    // run
+    fn resume(
+        &self,
+        mut program: Intcode,
+        mut bus: IoBus,
+        mut outputs: Vec<i64>,
+        steps: u64,
+    ) -> Outcome {
+        let result = program.execute_with_budget(
+            &mut bus,
+            |value| outputs.push(value),
+            &mut Budget::steps(steps),
+        );
+        outcome(&program, result, outputs)
+    }
+
+    // Runs the program without any symbols, comparing memory as far as the reference run
+    // got
+    fn symbolic(&self, expected: &Outcome) -> Outcome {
+        let mut program = SymbolicIntcode::new(&self.program());
+        for &value in &self.inputs {
+            program = program.with_input(value);
+        }
+        let result = match program.execute_with_budget(&mut Budget::steps(MAX_STEPS)) {
+            Ok(outcome) => Ok(outcome),
+            Err(SymbolicError::Vm(e)) => Err(e),
+            Err(e) => panic!("symbolic execution without symbols failed: {}", e),
+        };
+        let constant = |value: &Option<_>| {
+            value
+                .as_ref()
+                .and_then(LinearExpr::as_constant)
+                .expect("values without symbols are constant")
+        };
+        Outcome {
+            result,
+            outputs: program.outputs().iter().map(constant).collect(),
+            memory: (0..expected.memory.len())
+                .map(|address| constant(&program.read(address)))
+                .collect(),
+            ip: program.ip(),
+            relative_base: program.relative_base(),
+        }
+    }
+
+    fn transpiled(&self) -> Outcome {
+        let mut program = transpiled::Program::with_memory(self.memory.clone());
+        let mut outputs = Vec::new();
+        let result = program.execute_with_io(self.bus(), |value| outputs.push(value));
+        Outcome {
+            result,
+            outputs,
+            memory: trimmed(program.memory().to_vec()),
+            ip: program.ip(),
+            relative_base: program.relative_base(),
+        }
+    }
+
+    // Only provides an input once the program asks for it, sometimes snapshotting or
+    // cloning the program while it waits
+    fn one_input_at_a_time(&self, rng: &mut Rng) -> Outcome {
+        let mut program = self.program();
+        let mut budget = Budget::steps(MAX_STEPS);
+        let mut bus = IoBus::default();
+        let mut inputs = self.inputs.iter();
+        let mut outputs = Vec::new();
+        loop {
+            let result =
+                program.execute_with_budget(&mut bus, |value| outputs.push(value), &mut budget);
+            match (result, inputs.next()) {
+                (Ok(RunOutcome::NeedsInput), Some(&input)) => {
+                    if rng.chance(50) {
+                        let mut snapshot = Vec::new();
+                        program.save_snapshot(&mut snapshot).unwrap();
+                        program = Intcode::load_snapshot(&snapshot[..]).unwrap();
+                    } else {
+                        program = program.clone();
+                    }
+                    bus.write(input);
+                }
+                _ => return outcome(&program, result, outputs),
+            }
+        }
+    }
+
+    fn stopping_after_outputs(&self) -> Outcome {
+        let mut program = self.program();
+        let mut budget = Budget::steps(MAX_STEPS);
+        let mut bus = self.bus();
+        let mut outputs = Vec::new();
+        loop {
+            match program.run_with_budget(&mut bus, &mut budget) {
+                Ok(RunOutcome::Output(value)) => outputs.push(value),
+                result => return outcome(&program, result, outputs),
+            }
+        }
+    }
+}
+
+// A source parameter, which is usually a variable but sometimes a constant, code or
+// memory no instruction has written to
+fn source(rng: &mut Rng, data: i64) -> (ParameterMode, i64) {
+    match rng.below(20) {
+        0..=5 => (ParameterMode::Immediate, rng.between(-10, 10)),
+        6..=11 => (ParameterMode::Position, data + rng.below(DATA_WORDS) as i64),
+        12..=17 => (ParameterMode::Relative, rng.below(DATA_WORDS / 2) as i64),
+        18 => (ParameterMode::Position, rng.between(0, data - 1)),
+        _ => (ParameterMode::Position, FAR_ADDRESS + rng.between(0, 3)),
+    }
+}
+
+// A destination parameter, which is usually a variable but sometimes far away
+fn destination(rng: &mut Rng, data: i64) -> (ParameterMode, i64) {
+    match rng.below(20) {
+        0..=8 => (ParameterMode::Position, data + rng.below(DATA_WORDS) as i64),
+        9..=17 => (ParameterMode::Relative, rng.below(DATA_WORDS / 2) as i64),
+        _ => (ParameterMode::Position, FAR_ADDRESS + rng.between(0, 3)),
+    }
+}
+
+// The opcodes an instruction can be patched into
+fn replacements(opcode: Opcode) -> Option<&'static [Opcode]> {
+    match opcode {
+        Opcode::Add | Opcode::LessThan | Opcode::Equals => {
+            Some(&[Opcode::Add, Opcode::LessThan, Opcode::Equals])
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+            Some(&[Opcode::JumpIfTrue, Opcode::JumpIfFalse])
+        }
+        _ => None,
+    }
+}
+
+fn header(opcode: Opcode, parameters: &[(ParameterMode, i64)]) -> i64 {
+    let mut word = opcode.value();
+    let mut mode_multiplier = 100;
+    for &(mode, _) in parameters {
+        word += mode_multiplier * mode_digit(mode);
+        mode_multiplier *= 10;
+    }
+    word
+}
+
+fn outcome(program: &Intcode, result: Result<RunOutcome, VmError>, outputs: Vec<i64>) -> Outcome {
+    Outcome {
+        result,
+        outputs,
+        memory: trimmed(program.memory()),
+        ip: program.ip(),
+        relative_base: program.relative_base(),
+    }
+}
+
+// Snapshots don't keep trailing zeros, so they're not part of the outcome
+fn trimmed(mut memory: Vec<i64>) -> Vec<i64> {
+    let len = memory
+        .iter()
+        .rposition(|&value| value != 0)
+        .map_or(0, |i| i + 1);
+    memory.truncate(len);
+    memory
+}
+
+impl Display for FuzzFailure {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "seed {}: {} run ended differently",
+            self.seed, self.check
+        )?;
+        writeln!(f, "expected: {}", self.expected)?;
+        write!(f, "actual:   {}", self.actual)
+    }
+}
+
+impl Error for FuzzFailure {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::disassemble;
+
+    #[test]
+    pub fn test_generate() {
+        assert_eq!(FuzzCase::generate(7), FuzzCase::generate(7));
+        assert_ne!(FuzzCase::generate(7), FuzzCase::generate(8));
+
+        // Programs should mostly run to the end without errors, and not all stop straight
+        // away
+        let cases = (0..100).map(FuzzCase::generate).collect::<Vec<_>>();
+        let outcomes = cases
+            .iter()
+            .map(|case| case.reference())
+            .collect::<Vec<_>>();
+        let halted = outcomes
+            .iter()
+            .filter(|(outcome, _)| outcome.result == Ok(RunOutcome::Halted))
+            .count();
+        assert!(halted > 50, "only {} programs halted", halted);
+        assert!(outcomes.iter().all(|(outcome, _)| outcome.result.is_ok()));
+        assert!(outcomes.iter().any(|&(_, steps)| steps > 10));
+
+        // Jump targets should come in every mode. Everything before the first HALT is code.
+        for &mode in &["#", "[", "[rb"] {
+            assert!(cases.iter().any(|case| {
+                disassemble(&case.memory)
+                    .to_string()
+                    .lines()
+                    .take_while(|line| !line.ends_with("HALT"))
+                    .filter(|line| line.contains(" JZ ") || line.contains(" JNZ "))
+                    .any(|line| line.split(", ").nth(1).unwrap().starts_with(mode))
+            }));
+        }
+    }
+
+    #[test]
+    pub fn test_fuzz() {
+        for seed in 0..500 {
+            if let Err(failure) = FuzzCase::generate(seed).check() {
+                panic!("{}", failure);
+            }
+        }
+    }
+}
@@ -0,0 +1,451 @@
+// Generated by `intcode-transpile` from a 116-word Intcode image. Do not edit.
+//
+// Instructions found in the image are compiled into the `match` in `resume`. Each one
+// checks that its opcode and parameter modes haven't been overwritten before it runs,
+// and anything else is left to `step`, which interprets one instruction at a time.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::intcode::{Input, Output, RunOutcome, VmError};
+
+// Writes from this address on are kept in a map rather than growing memory
+const FAR_MEMORY: usize = 1 << 24;
+
+#[rustfmt::skip]
+const IMAGE: [i64; 116] = [
+    109, 65, 3, 68, 4, 77, 203, 4, 1106, 5, 33, 107,
+    -9, 18, 5001, 104, 9, 109, 0, 1101, 106, 0, 59, 20008,
+    71, 23, 7, 203, 1, 20102, 1, 72, 4, 3, 68, 21001,
+    73, 4, 6, 203, 7, 109, 1, 2106, 4, 48, 1101, 1005,
+    0, 50, 1006, 76, 59, 4, 5003, 4, 69, 104, -5, 106,
+    -5, 115, 3, 5001, 99, -6, -16, 6, -13, 6, 20, 2,
+    -10, 0, 4, -17, -1, 6, 0, 11, 1, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 57, 57, 62,
+];
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    ip: usize,
+    relative_base: i64,
+    halted: bool,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::with_memory(IMAGE.to_vec())
+    }
+
+    // Runs the compiled code on a different image, such as a patched copy of the original
+    pub fn with_memory(memory: Vec<i64>) -> Self {
+        Self {
+            memory,
+            far: HashMap::new(),
+            ip: 0,
+            relative_base: 0,
+            halted: false,
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Memory up to the highest address written below `FAR_MEMORY`
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(&value) => value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<RunOutcome, VmError> {
+        self.execute_with_io(|| 0, |_| {})
+    }
+
+    // Runs until the program halts or needs input that isn't available
+    pub fn execute_with_io<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut output, false)
+    }
+
+    // Runs until the program produces an output, halts or needs input that isn't available
+    pub fn run<I: Input>(&mut self, mut input: I) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut |_| {}, true)
+    }
+
+    fn resume<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        stop_on_output: bool,
+    ) -> Result<RunOutcome, VmError> {
+        if self.halted {
+            return Ok(RunOutcome::Halted);
+        }
+        loop {
+            match self.ip {
+                0 if self.read(0) == 109 => {
+                    self.relative_base += self.read(1);
+                    self.ip = 2;
+                }
+                2 if self.read(2) == 3 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.position(self.read(3))?;
+                    self.store(address, value);
+                    self.ip = 4;
+                }
+                4 if self.read(4) == 4 => {
+                    let value = self.read(self.position(self.read(5))?);
+                    output.receive_output(value);
+                    self.ip = 6;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                6 if self.read(6) == 203 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.relative(self.read(7))?;
+                    self.store(address, value);
+                    self.ip = 8;
+                }
+                8 if self.read(8) == 1106 => {
+                    if self.read(9) == 0 {
+                        self.ip = self.jump(self.read(10))?;
+                    } else {
+                        self.ip = 11;
+                    }
+                }
+                11 if self.read(11) == 107 => {
+                    let a = self.read(12);
+                    let b = self.read(self.position(self.read(13))?);
+                    let address = self.position(self.read(14))?;
+                    self.store(address, i64::from(a < b));
+                    self.ip = 15;
+                }
+                15 if self.read(15) == 104 => {
+                    let value = self.read(16);
+                    output.receive_output(value);
+                    self.ip = 17;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                17 if self.read(17) == 109 => {
+                    self.relative_base += self.read(18);
+                    self.ip = 19;
+                }
+                19 if self.read(19) == 1101 => {
+                    let a = self.read(20);
+                    let b = self.read(21);
+                    let address = self.position(self.read(22))?;
+                    self.store(address, a + b);
+                    self.ip = 23;
+                }
+                23 if self.read(23) == 20008 => {
+                    let a = self.read(self.position(self.read(24))?);
+                    let b = self.read(self.position(self.read(25))?);
+                    let address = self.relative(self.read(26))?;
+                    self.store(address, i64::from(a == b));
+                    self.ip = 27;
+                }
+                27 if self.read(27) == 203 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.relative(self.read(28))?;
+                    self.store(address, value);
+                    self.ip = 29;
+                }
+                29 if self.read(29) == 20102 => {
+                    let a = self.read(30);
+                    let b = self.read(self.position(self.read(31))?);
+                    let address = self.relative(self.read(32))?;
+                    self.store(address, a * b);
+                    self.ip = 33;
+                }
+                33 if self.read(33) == 3 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.position(self.read(34))?;
+                    self.store(address, value);
+                    self.ip = 35;
+                }
+                35 if self.read(35) == 21001 => {
+                    let a = self.read(self.position(self.read(36))?);
+                    let b = self.read(37);
+                    let address = self.relative(self.read(38))?;
+                    self.store(address, a + b);
+                    self.ip = 39;
+                }
+                39 if self.read(39) == 203 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.relative(self.read(40))?;
+                    self.store(address, value);
+                    self.ip = 41;
+                }
+                41 if self.read(41) == 109 => {
+                    self.relative_base += self.read(42);
+                    self.ip = 43;
+                }
+                43 if self.read(43) == 2106 => {
+                    if self.read(44) == 0 {
+                        self.ip = self.jump(self.read(self.relative(self.read(45))?))?;
+                    } else {
+                        self.ip = 46;
+                    }
+                }
+                46 if self.read(46) == 1101 => {
+                    let a = self.read(47);
+                    let b = self.read(48);
+                    let address = self.position(self.read(49))?;
+                    self.store(address, a + b);
+                    self.ip = 50;
+                }
+                50 if self.read(50) == 1006 => {
+                    if self.read(self.position(self.read(51))?) == 0 {
+                        self.ip = self.jump(self.read(52))?;
+                    } else {
+                        self.ip = 53;
+                    }
+                }
+                53 if self.read(53) == 4 => {
+                    let value = self.read(self.position(self.read(54))?);
+                    output.receive_output(value);
+                    self.ip = 55;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                55 if self.read(55) == 4 => {
+                    let value = self.read(self.position(self.read(56))?);
+                    output.receive_output(value);
+                    self.ip = 57;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                57 if self.read(57) == 104 => {
+                    let value = self.read(58);
+                    output.receive_output(value);
+                    self.ip = 59;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                59 if self.read(59) == 106 => {
+                    if self.read(60) == 0 {
+                        self.ip = self.jump(self.read(self.position(self.read(61))?))?;
+                    } else {
+                        self.ip = 62;
+                    }
+                }
+                62 if self.read(62) == 3 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.position(self.read(63))?;
+                    self.store(address, value);
+                    self.ip = 64;
+                }
+                64 if self.read(64) == 99 => {
+                    self.halted = true;
+                    return Ok(RunOutcome::Halted);
+                }
+                67 if self.read(67) == 6 => {
+                    if self.read(self.position(self.read(68))?) == 0 {
+                        self.ip = self.jump(self.read(self.position(self.read(69))?))?;
+                    } else {
+                        self.ip = 70;
+                    }
+                }
+                71 if self.read(71) == 2 => {
+                    let a = self.read(self.position(self.read(72))?);
+                    let b = self.read(self.position(self.read(73))?);
+                    let address = self.position(self.read(74))?;
+                    self.store(address, a * b);
+                    self.ip = 75;
+                }
+                77 if self.read(77) == 6 => {
+                    if self.read(self.position(self.read(78))?) == 0 {
+                        self.ip = self.jump(self.read(self.position(self.read(79))?))?;
+                    } else {
+                        self.ip = 80;
+                    }
+                }
+                80 if self.read(80) == 1 => {
+                    let a = self.read(self.position(self.read(81))?);
+                    let b = self.read(self.position(self.read(82))?);
+                    let address = self.position(self.read(83))?;
+                    self.store(address, a + b);
+                    self.ip = 84;
+                }
+                _ => match self.step(input, output)? {
+                    Some(RunOutcome::Output(_)) if !stop_on_output => {}
+                    Some(outcome) => return Ok(outcome),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    // Interprets the instruction at `ip`, returning why the program stopped or the
+    // value it output, if any
+    fn step<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Option<RunOutcome>, VmError> {
+        let ip = self.ip;
+        let header = self.read(ip);
+        let opcode = header % 100;
+        let parameters = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(VmError::UnknownOpcode { opcode: header, ip }),
+        };
+        let mut modes = [0; 3];
+        let mut digits = header / 100;
+        for mode in modes.iter_mut().take(parameters) {
+            *mode = digits % 10;
+            if !(0..=2).contains(mode) {
+                return Err(VmError::InvalidParameterMode { mode: *mode, ip });
+            }
+            digits /= 10;
+        }
+
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let a = self.operand(modes[0], ip + 1)?;
+                let b = self.operand(modes[1], ip + 2)?;
+                let address = self.address(modes[2], ip + 3)?;
+                let value = match opcode {
+                    1 => a + b,
+                    2 => a * b,
+                    7 => i64::from(a < b),
+                    _ => i64::from(a == b),
+                };
+                self.store(address, value);
+            }
+            3 => {
+                let value = match input.get_input() {
+                    Some(value) => value,
+                    None => return Ok(Some(RunOutcome::NeedsInput)),
+                };
+                let address = self.address(modes[0], ip + 1)?;
+                self.store(address, value);
+            }
+            4 => {
+                let value = self.operand(modes[0], ip + 1)?;
+                output.receive_output(value);
+                self.ip = ip + 2;
+                return Ok(Some(RunOutcome::Output(value)));
+            }
+            5 | 6 => {
+                if (self.operand(modes[0], ip + 1)? != 0) == (opcode == 5) {
+                    self.ip = self.jump(self.operand(modes[1], ip + 2)?)?;
+                    return Ok(None);
+                }
+            }
+            9 => self.relative_base += self.operand(modes[0], ip + 1)?,
+            _ => {
+                self.halted = true;
+                return Ok(Some(RunOutcome::Halted));
+            }
+        }
+        self.ip = ip + 1 + parameters;
+        Ok(None)
+    }
+
+    fn operand(&self, mode: i64, word: usize) -> Result<i64, VmError> {
+        let value = self.read(word);
+        match mode {
+            0 => Ok(self.read(self.position(value)?)),
+            1 => Ok(value),
+            _ => Ok(self.read(self.relative(value)?)),
+        }
+    }
+
+    fn address(&self, mode: i64, word: usize) -> Result<usize, VmError> {
+        match mode {
+            0 => self.position(self.read(word)),
+            1 => Err(VmError::WriteToImmediate { ip: self.ip }),
+            _ => self.relative(self.read(word)),
+        }
+    }
+
+    fn position(&self, address: i64) -> Result<usize, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
+            });
+        }
+        Ok(address as usize)
+    }
+
+    fn relative(&self, offset: i64) -> Result<usize, VmError> {
+        self.position(self.relative_base + offset)
+    }
+
+    fn jump(&self, target: i64) -> Result<usize, VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.ip,
+            });
+        }
+        Ok(target as usize)
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        if address < self.memory.len() {
+            self.memory[address] = value;
+        } else if address < FAR_MEMORY {
+            self.memory.resize(address + 1, 0);
+            self.memory[address] = value;
+        } else {
+            self.far.insert(address, value);
+        }
+    }
+}
@@ -0,0 +1,591 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::sync::Arc;
+
+use super::{
+    Header, Input, Intcode, MemoryWrite, Op, Opcode, Output, Parameter, State, TraceEvent, VmError,
+    MAX_PARAMETERS,
+};
+
+type Handler = Arc<dyn Fn(&mut Execution) -> Result<(), VmError> + Send + Sync>;
+
+// The opcodes a VM understands. The default set is just the built-in opcodes, and
+// `with_opcode` adds more, each run by a handler.
+#[derive(Clone, Default)]
+pub struct InstructionSet {
+    extensions: BTreeMap<i64, (Extension, Handler)>,
+}
+
+// Why an opcode couldn't be added to an instruction set
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OpcodeError {
+    // The opcode isn't one or two digits
+    InvalidValue(i64),
+    AlreadyDefined(i64),
+    MnemonicInUse(&'static str),
+    TooManyParameters(&'static str),
+    // The parameter the opcode writes to is past the last one
+    MissingWriteParameter(&'static str),
+}
+
+// How an added opcode is decoded
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct Extension {
+    value: u8,
+    mnemonic: &'static str,
+    parameter_count: u8,
+    write_parameter: Option<u8>,
+}
+
+// What the handler of an added opcode can see and do. Once the handler returns, the
+// program continues with the next instruction unless the handler jumped or halted.
+pub struct Execution<'a> {
+    program: &'a mut Intcode,
+    op: Op,
+    input: &'a mut dyn Input,
+    output: &'a mut dyn Output,
+    event: &'a mut Option<TraceEvent>,
+    next_ip: usize,
+    // Whether the instruction ran out of input
+    waiting: bool,
+}
+
+impl InstructionSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds an opcode with the given number of parameters, `write_parameter` being the
+    // index of the parameter it writes its result to, if any, which can't be in immediate
+    // mode. Fails if `value` or `mnemonic` is already taken, including by a built-in
+    // opcode, if `value` isn't one or two digits, or if the parameters don't fit in an
+    // instruction.
+    pub fn with_opcode<F>(
+        mut self,
+        value: i64,
+        mnemonic: &'static str,
+        parameter_count: usize,
+        write_parameter: Option<usize>,
+        handler: F,
+    ) -> Result<Self, OpcodeError>
+    where
+        F: Fn(&mut Execution) -> Result<(), VmError> + Send + Sync + 'static,
+    {
+        if !(1..100).contains(&value) {
+            return Err(OpcodeError::InvalidValue(value));
+        }
+        if self.opcode(value).is_some() {
+            return Err(OpcodeError::AlreadyDefined(value));
+        }
+        if self.opcode_named(mnemonic).is_some() {
+            return Err(OpcodeError::MnemonicInUse(mnemonic));
+        }
+        if parameter_count > MAX_PARAMETERS {
+            return Err(OpcodeError::TooManyParameters(mnemonic));
+        }
+        if write_parameter.is_some_and(|i| i >= parameter_count) {
+            return Err(OpcodeError::MissingWriteParameter(mnemonic));
+        }
+        let extension = Extension {
+            value: value as u8,
+            mnemonic,
+            parameter_count: parameter_count as u8,
+            write_parameter: write_parameter.map(|i| i as u8),
+        };
+        self.extensions
+            .insert(value, (extension, Arc::new(handler)));
+        Ok(self)
+    }
+
+    // The opcode with the given number, whether it's built in or added
+    pub fn opcode(&self, value: i64) -> Option<Opcode> {
+        Opcode::from_value(value).or_else(|| {
+            self.extensions
+                .get(&value)
+                .map(|&(extension, _)| Opcode::Extended(extension))
+        })
+    }
+
+    // Every opcode in the set, ordered by number
+    pub fn opcodes(&self) -> Vec<Opcode> {
+        let mut opcodes = Opcode::ALL.to_vec();
+        opcodes.extend(
+            self.extensions
+                .values()
+                .map(|&(extension, _)| Opcode::Extended(extension)),
+        );
+        opcodes.sort_by_key(|opcode| opcode.value());
+        opcodes
+    }
+
+    // The opcode with the given mnemonic, ignoring case
+    pub fn opcode_named(&self, mnemonic: &str) -> Option<Opcode> {
+        self.opcodes()
+            .into_iter()
+            .find(|opcode| opcode.mnemonic().eq_ignore_ascii_case(mnemonic))
+    }
+
+    // Decodes an instruction word, whether its opcode is built in or added
+    pub(super) fn decode(&self, word: i64, ip: usize) -> Result<Header, VmError> {
+        match self.opcode(word % 100) {
+            Some(opcode) => Header::decode_modes(opcode, word, ip),
+            None => Err(VmError::UnknownOpcode { opcode: word, ip }),
+        }
+    }
+
+    pub(super) fn execute(
+        &self,
+        extension: Extension,
+        execution: &mut Execution,
+    ) -> Result<(), VmError> {
+        let (_, handler) = &self.extensions[&extension.value()];
+        handler(execution)
+    }
+}
+
+impl Debug for InstructionSet {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.extensions.values().map(|(extension, _)| extension))
+            .finish()
+    }
+}
+
+impl Display for OpcodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            OpcodeError::InvalidValue(value) => {
+                write!(f, "opcode {} isn't one or two digits", value)
+            }
+            OpcodeError::AlreadyDefined(value) => write!(f, "opcode {} is already defined", value),
+            OpcodeError::MnemonicInUse(mnemonic) => {
+                write!(f, "mnemonic {} is already in use", mnemonic)
+            }
+            OpcodeError::TooManyParameters(mnemonic) => write!(
+                f,
+                "{} takes more than {} parameters",
+                mnemonic, MAX_PARAMETERS
+            ),
+            OpcodeError::MissingWriteParameter(mnemonic) => {
+                write!(f, "{} writes to a parameter it doesn't have", mnemonic)
+            }
+        }
+    }
+}
+
+impl Error for OpcodeError {}
+
+impl Extension {
+    pub fn value(self) -> i64 {
+        self.value.into()
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        self.mnemonic
+    }
+
+    pub fn parameter_count(self) -> usize {
+        self.parameter_count.into()
+    }
+
+    pub fn write_parameter(self) -> Option<usize> {
+        self.write_parameter.map(usize::from)
+    }
+}
+
+impl<'a> Execution<'a> {
+    pub(super) fn new(
+        program: &'a mut Intcode,
+        op: Op,
+        input: &'a mut dyn Input,
+        output: &'a mut dyn Output,
+        event: &'a mut Option<TraceEvent>,
+        next_ip: usize,
+    ) -> Self {
+        Self {
+            program,
+            op,
+            input,
+            output,
+            event,
+            next_ip,
+            waiting: false,
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.program.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.program.relative_base
+    }
+
+    // The value of parameter `i`, which is read from memory unless the parameter is in
+    // immediate mode
+    pub fn load(&mut self, i: usize) -> Result<i64, VmError> {
+        let parameter = self.parameter(i);
+        self.program.load_traced(parameter, self.event)
+    }
+
+    // Writes to the address parameter `i` refers to
+    pub fn store(&mut self, i: usize, value: i64) -> Result<(), VmError> {
+        let parameter = self.parameter(i);
+        self.program.write_traced(parameter, value, self.event)
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        self.program.memory.read(address)
+    }
+
+    pub fn write(&mut self, address: usize, value: i64) -> Result<(), VmError> {
+        let old_value = self.program.memory.read(address);
+        if self.program.memory.write(address, value) {
+            if let Some(event) = self.event.as_mut() {
+                event.writes.push(MemoryWrite {
+                    address,
+                    old_value,
+                    value,
+                });
+            }
+            Ok(())
+        } else {
+            Err(VmError::MemoryLimitExceeded {
+                address,
+                ip: self.program.ip,
+            })
+        }
+    }
+
+    // Reads the next input, like IN. If there isn't any, the program stops to wait for
+    // it once the handler returns, and the whole instruction runs again when the program
+    // resumes. Anything the handler did before then isn't undone, including reading
+    // earlier inputs, so handlers should read their input before doing anything else.
+    pub fn input(&mut self) -> Option<i64> {
+        let value = self.input.get_input();
+        match value {
+            Some(value) => {
+                if let Some(event) = self.event.as_mut() {
+                    event.inputs.push(value);
+                }
+            }
+            None => self.waiting = true,
+        }
+        value
+    }
+
+    pub fn output(&mut self, value: i64) {
+        self.output.receive_output(value);
+        if let Some(event) = self.event.as_mut() {
+            event.outputs.push(value);
+        }
+    }
+
+    // Continues at `target` rather than the next instruction
+    pub fn jump(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.program.ip,
+            });
+        }
+        self.next_ip = target as usize;
+        Ok(())
+    }
+
+    // Stops the program once this instruction is done, like HALT
+    pub fn halt(&mut self) {
+        self.program.state = State::Halted;
+    }
+
+    pub(super) fn next_ip(&self) -> usize {
+        self.next_ip
+    }
+
+    pub(super) fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    // Panics if the instruction doesn't have a parameter `i`
+    fn parameter(&self, i: usize) -> Parameter {
+        self.op.parameters()[i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{
+        assemble, assemble_with, control_flow_graph_with, disassemble_with, Debugger, Exit, IoBus,
+        MemoryAccess, Profile, RunOutcome, StopReason, Target, Transcript, Watch,
+    };
+
+    // Adds HALTC, which outputs an exit code and halts, and COPY, which copies a block of
+    // memory from one address to another
+    fn instruction_set() -> InstructionSet {
+        InstructionSet::new()
+            .with_opcode(10, "HALTC", 1, None, |execution| {
+                let code = execution.load(0)?;
+                execution.output(code);
+                execution.halt();
+                Ok(())
+            })
+            .unwrap()
+            .with_opcode(11, "COPY", 3, None, |execution| {
+                let (from, to, len) = (execution.load(0)?, execution.load(1)?, execution.load(2)?);
+                for i in 0..len {
+                    let value = execution.read((from + i) as usize);
+                    execution.write((to + i) as usize, value)?;
+                }
+                Ok(())
+            })
+            .unwrap()
+            .with_opcode(12, "SKIPZ", 2, Some(1), |execution| {
+                // Skips the two words after it if the first parameter is zero, saving the
+                // condition where the second parameter points
+                let condition = execution.load(0)?;
+                execution.store(1, condition)?;
+                if condition == 0 {
+                    execution.jump(execution.ip() as i64 + 5)?;
+                }
+                Ok(())
+            })
+            .unwrap()
+    }
+
+    #[test]
+    pub fn test_opcodes() {
+        let instructions = instruction_set();
+        assert_eq!(Some(Opcode::Add), instructions.opcode(1));
+        assert_eq!(None, instructions.opcode(13));
+        let copy = instructions.opcode(11).unwrap();
+        assert_eq!(
+            ("COPY", 3, None),
+            (
+                copy.mnemonic(),
+                copy.parameter_count(),
+                copy.write_parameter()
+            )
+        );
+        let values = instructions
+            .opcodes()
+            .iter()
+            .map(|opcode| opcode.value())
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 99], values);
+        assert_eq!(10, InstructionSet::default().opcodes().len());
+    }
+
+    #[test]
+    pub fn test_invalid_opcodes() {
+        let add = |value, mnemonic, parameter_count, write_parameter| {
+            instruction_set()
+                .with_opcode(
+                    value,
+                    mnemonic,
+                    parameter_count,
+                    write_parameter,
+                    |_| Ok(()),
+                )
+                .map(|_| ())
+        };
+        assert_eq!(Err(OpcodeError::AlreadyDefined(9)), add(9, "NOP", 0, None));
+        assert_eq!(
+            Err(OpcodeError::AlreadyDefined(11)),
+            add(11, "NOP", 0, None)
+        );
+        assert_eq!(
+            Err(OpcodeError::MnemonicInUse("add")),
+            add(13, "add", 0, None)
+        );
+        assert_eq!(
+            Err(OpcodeError::MnemonicInUse("COPY")),
+            add(13, "COPY", 0, None)
+        );
+        assert_eq!(
+            Err(OpcodeError::InvalidValue(100)),
+            add(100, "NOP", 0, None)
+        );
+        assert_eq!(Err(OpcodeError::InvalidValue(0)), add(0, "NOP", 0, None));
+        assert_eq!(
+            Err(OpcodeError::TooManyParameters("NOP")),
+            add(13, "NOP", 4, None)
+        );
+        assert_eq!(
+            Err(OpcodeError::MissingWriteParameter("NOP")),
+            add(13, "NOP", 1, Some(1))
+        );
+        assert_eq!(Ok(()), add(13, "NOP", 0, None));
+        assert_eq!(
+            "opcode 9 is already defined",
+            OpcodeError::AlreadyDefined(9).to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_extended_program() {
+        // Copies three words, skips the OUT because the first copied word is zero, then
+        // exits with the last one
+        let memory = vec![
+            11111, 12, 20, 3, // COPY #12, #20, #3
+            12, 20, 23, // SKIPZ [20] -> [23]
+            4, 21, // OUT [21]
+            10, 22, // HALTC [22]
+            99, // HALT
+            0, 5, 42,
+        ];
+        let mut program = Intcode::new(memory.clone()).with_instruction_set(instruction_set());
+        let mut outputs = Vec::new();
+        let mut transcript = Transcript::new();
+        let outcome = program
+            .execute_traced(|| 0, |value| outputs.push(value), &mut transcript)
+            .unwrap();
+        assert_eq!(RunOutcome::Halted, outcome);
+        assert_eq!(vec![42], outputs);
+        assert_eq!(vec![42], transcript.outputs().collect::<Vec<_>>());
+        assert_eq!(&[0, 5, 42, 0], &program.memory()[20..24]);
+        assert_eq!(9, program.ip());
+
+        // Without the extensions, the program fails on the first instruction
+        let mut program = Intcode::new(memory);
+        assert_eq!(
+            Err(VmError::UnknownOpcode {
+                opcode: 11111,
+                ip: 0
+            }),
+            program.execute()
+        );
+    }
+
+    #[test]
+    pub fn test_extended_io() {
+        // INADD reads a value and adds its first parameter to it, and OUT2 outputs both
+        // of its parameters
+        let instructions = InstructionSet::new()
+            .with_opcode(13, "INADD", 2, Some(1), |execution| {
+                let value = match execution.input() {
+                    Some(value) => value,
+                    None => return Ok(()),
+                };
+                let sum = value + execution.load(0)?;
+                execution.store(1, sum)
+            })
+            .unwrap()
+            .with_opcode(14, "OUT2", 2, None, |execution| {
+                let (a, b) = (execution.load(0)?, execution.load(1)?);
+                execution.output(a);
+                execution.output(b);
+                Ok(())
+            })
+            .unwrap();
+        // INADD #5 -> [10], OUT2 [10], #7
+        let mut program = Intcode::new(vec![113, 5, 10, 1014, 10, 7, 99, 0, 0, 0, 0])
+            .with_instruction_set(instructions);
+        let mut input = IoBus::default();
+        assert_eq!(
+            Ok(RunOutcome::NeedsInput),
+            program.execute_with_io(&mut input, |_| {})
+        );
+        assert_eq!(0, program.ip());
+
+        input.write(3);
+        let mut outputs = Vec::new();
+        let mut transcript = Transcript::new();
+        let outcome =
+            program.execute_traced(&mut input, |value| outputs.push(value), &mut transcript);
+        assert_eq!(Ok(RunOutcome::Halted), outcome);
+        assert_eq!(vec![8, 7], outputs);
+        assert_eq!(vec![3], transcript.inputs().collect::<Vec<_>>());
+        assert_eq!(vec![8, 7], transcript.outputs().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_extended_writes() {
+        // COPY #12, #20, #3, then the three words it copies
+        let program = Intcode::new(vec![11111, 12, 20, 3, 99, 0, 0, 0, 0, 0, 0, 0, 0, 5, 42])
+            .with_instruction_set(instruction_set());
+        let mut profile = Profile::new();
+        program
+            .clone()
+            .execute_traced(|| 0, |_| {}, &mut profile)
+            .unwrap();
+        assert_eq!(
+            (1, 1, 1),
+            (profile.writes(20), profile.writes(21), profile.writes(22))
+        );
+
+        let mut debugger = Debugger::new(program);
+        debugger.add_watchpoint(21, Watch::Write);
+        assert_eq!(
+            Ok(StopReason::Watchpoint {
+                address: 21,
+                access: MemoryAccess::Write,
+                ip: 0
+            }),
+            debugger.resume(IoBus::default(), IoBus::default())
+        );
+        assert_eq!(vec![0, 5, 42], debugger.memory(20..23));
+        assert_eq!(1, debugger.rewind(1).steps);
+        assert_eq!(0, debugger.ip());
+        assert_eq!(vec![0, 0, 0], debugger.memory(20..23));
+    }
+
+    #[test]
+    pub fn test_extended_errors_and_profile() {
+        let instructions = instruction_set();
+        // SKIPZ can't write to an immediate parameter
+        let mut program = Intcode::new(vec![11012, 0, 0]).with_instruction_set(instructions);
+        assert_eq!(Err(VmError::WriteToImmediate { ip: 0 }), program.execute());
+
+        let mut program = Intcode::new(vec![1010, 7]).with_instruction_set(instruction_set());
+        let mut profile = Profile::new();
+        program.execute_traced(|| 0, |_| {}, &mut profile).unwrap();
+        assert_eq!(
+            1,
+            profile.opcode_count(program.instruction_set().opcode(10).unwrap())
+        );
+        assert_eq!(1, profile.outputs());
+    }
+
+    #[test]
+    pub fn test_extended_listing() {
+        let instructions = instruction_set();
+        let memory = vec![11111, 12, 20, 3, 12, 20, 23, 4, 21, 10, 22, 99, 0, 5, 42];
+        let listing = disassemble_with(&memory, &instructions).to_string();
+        assert_eq!(
+            "0000: COPY #12, #20, #3
+0004: SKIPZ [20] -> [23]
+0007: OUT [21]
+0009: HALTC [22]
+0011: HALT
+0012: DATA 0
+0013: DATA 5
+0014: DATA 42
+",
+            listing
+        );
+        assert_eq!(Ok(memory.clone()), assemble_with(&listing, &instructions));
+        assert!(assemble(&listing).is_err());
+
+        // The added instructions could jump anywhere
+        let computed = |next| Exit::Jump {
+            target: Target::Computed,
+            next: Some(next),
+        };
+        let blocks = control_flow_graph_with(&memory, &instructions)
+            .blocks()
+            .iter()
+            .map(|block| (block.instructions.clone(), block.exit))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                (vec![0], computed(4)),
+                (vec![4], computed(7)),
+                (vec![7, 9], computed(11)),
+                (vec![11], Exit::Halt),
+            ],
+            blocks
+        );
+    }
+}
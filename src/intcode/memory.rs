@@ -0,0 +1,407 @@
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Range;
+use std::sync::Arc;
+
+use super::{Header, VmError};
+
+const PAGE_BITS: usize = 6;
+const PAGE_SIZE: usize = 1 << PAGE_BITS;
+const OFFSET_MASK: usize = PAGE_SIZE - 1;
+
+// The memory limit of a new VM, in words. Every puzzle fits in a tiny fraction of this,
+// but a dense VM writing to a wild address fails instead of exhausting the host's memory.
+const DEFAULT_LIMIT: usize = 1 << 24;
+
+// How a VM keeps track of the pages of memory it has written to
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum MemoryModel {
+    // A table indexed by page number. This is the fastest, but the table grows with the
+    // highest address written, so writing far addresses can exhaust memory.
+    Dense,
+    // A map from page number to page, which only takes space for the pages written and
+    // so copes with any address a program throws at it, at the cost of slower accesses.
+    Sparse,
+}
+
+// Program memory. The program image a VM was created from is shared by every clone of
+// it, and writes go to private copies of fixed-size pages of that image, so forking a
+// program only costs as much as the pages it has written to so far.
+#[derive(Clone)]
+pub(super) struct Memory {
+    image: Arc<Image>,
+    pages: Pages,
+    len: usize,
+    // The most words the pages may take up, see `footprint`
+    limit: usize,
+}
+
+// Pages that have been written to, keyed by page number
+#[derive(Clone)]
+enum Pages {
+    Dense(Vec<Option<Box<Page>>>),
+    Sparse(HashMap<usize, Box<Page>>),
+}
+
+struct Image {
+    words: Vec<i64>,
+    // The image is decoded up front, since it can't be written to once it's shared
+    decoded: Vec<Option<Header>>,
+}
+
+#[derive(Clone)]
+struct Page {
+    words: [i64; PAGE_SIZE],
+    // Decoded instruction headers, cleared whenever the word they came from is written
+    decoded: [Option<Header>; PAGE_SIZE],
+}
+
+impl Memory {
+    pub(super) fn new(words: &[i64]) -> Self {
+        let decoded = words
+            .iter()
+            .enumerate()
+            .map(|(address, &word)| Header::decode(word, address).ok())
+            .collect();
+        Self {
+            image: Arc::new(Image {
+                words: words.to_vec(),
+                decoded,
+            }),
+            pages: Pages::Dense(Vec::new()),
+            len: words.len(),
+            limit: DEFAULT_LIMIT,
+        }
+    }
+
+    pub(super) fn model(&self) -> MemoryModel {
+        match self.pages {
+            Pages::Dense(_) => MemoryModel::Dense,
+            Pages::Sparse(_) => MemoryModel::Sparse,
+        }
+    }
+
+    pub(super) fn set_model(&mut self, model: MemoryModel) {
+        if model == self.model() {
+            return;
+        }
+        self.pages = match mem::replace(&mut self.pages, Pages::Dense(Vec::new())) {
+            Pages::Dense(pages) => Pages::Sparse(
+                pages
+                    .into_iter()
+                    .enumerate()
+                    .filter_map(|(index, page)| Some((index, page?)))
+                    .collect(),
+            ),
+            Pages::Sparse(pages) => {
+                let mut table = Vec::new();
+                for (index, page) in pages {
+                    if index >= table.len() {
+                        table.resize_with(index + 1, || None);
+                    }
+                    table[index] = Some(page);
+                }
+                Pages::Dense(table)
+            }
+        };
+    }
+
+    pub(super) fn limit(&self) -> usize {
+        self.limit
+    }
+
+    // Writes that would make `footprint` exceed the limit fail instead. Lowering the limit
+    // below the current footprint doesn't free anything.
+    pub(super) fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    // The number of words taken up by pages: for dense memory this includes pages that
+    // haven't been written to below the highest one that has.
+    pub(super) fn footprint(&self) -> usize {
+        let pages = match &self.pages {
+            Pages::Dense(pages) => pages.len(),
+            Pages::Sparse(pages) => pages.len(),
+        };
+        pages * PAGE_SIZE
+    }
+
+    // One past the highest address that was part of the image or has been written to
+    pub(super) fn len(&self) -> usize {
+        self.len
+    }
+
+    // Only meant for undoing writes: memory past the new length should hold zeros again
+    pub(super) fn set_len(&mut self, len: usize) {
+        self.len = len;
+    }
+
+    #[inline]
+    pub(super) fn read(&self, address: usize) -> i64 {
+        match self.pages.get(address >> PAGE_BITS) {
+            Some(page) => page.words[address & OFFSET_MASK],
+            None => self.image.words.get(address).copied().unwrap_or(0),
+        }
+    }
+
+    // Returns `false` if the write would exceed the memory limit
+    #[inline]
+    #[must_use]
+    pub(super) fn write(&mut self, address: usize, value: i64) -> bool {
+        match self.word_mut(address) {
+            Some(word) => {
+                *word = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Returns `None` if making the word writable would exceed the memory limit
+    #[inline]
+    pub(super) fn word_mut(&mut self, address: usize) -> Option<&mut i64> {
+        let index = address >> PAGE_BITS;
+        if self.pages.get(index).is_none() && !self.copy_page(index) {
+            return None;
+        }
+        self.len = self.len.max(address + 1);
+        let page = self.pages.get_mut(index).expect("the page was just copied");
+        let offset = address & OFFSET_MASK;
+        page.decoded[offset] = None;
+        Some(&mut page.words[offset])
+    }
+
+    // Decodes the instruction word at an address, using the cached header if there is one
+    #[inline]
+    pub(super) fn header(&mut self, address: usize) -> Result<Header, VmError> {
+        let cached = match self.pages.get(address >> PAGE_BITS) {
+            Some(page) => page.decoded[address & OFFSET_MASK],
+            None => self.image.decoded.get(address).copied().flatten(),
+        };
+        match cached {
+            Some(header) => Ok(header),
+            None => self.decode(address),
+        }
+    }
+
+    #[cold]
+    fn decode(&mut self, address: usize) -> Result<Header, VmError> {
+        let header = Header::decode(self.read(address), address)?;
+        if let Some(page) = self.pages.get_mut(address >> PAGE_BITS) {
+            page.decoded[address & OFFSET_MASK] = Some(header);
+        }
+        Ok(header)
+    }
+
+    pub(super) fn to_vec(&self) -> Vec<i64> {
+        (0..self.len()).map(|address| self.read(address)).collect()
+    }
+
+    // The parts of memory that can hold anything but zeros, as runs of words in address
+    // order: the image and the pages written outside of it, with zeros trimmed from both
+    // ends. Unlike `to_vec`, this only takes as much space as the image and the pages.
+    pub(super) fn runs(&self) -> Vec<(usize, Vec<i64>)> {
+        let mut ranges = self
+            .pages
+            .indices()
+            .into_iter()
+            .map(|index| index << PAGE_BITS..(index + 1) << PAGE_BITS)
+            .collect::<Vec<_>>();
+        ranges.push(0..self.image.words.len());
+        ranges.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::new();
+        for range in ranges {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+        merged
+            .into_iter()
+            .filter_map(|range| {
+                let words = range.clone().map(|a| self.read(a)).collect::<Vec<_>>();
+                let first = words.iter().position(|&word| word != 0)?;
+                let last = words.iter().rposition(|&word| word != 0)?;
+                Some((range.start + first, words[first..=last].to_vec()))
+            })
+            .collect()
+    }
+
+    // Returns `false` if the page would exceed the memory limit
+    #[cold]
+    fn copy_page(&mut self, index: usize) -> bool {
+        let footprint = match &self.pages {
+            Pages::Dense(pages) => pages.len().max(index + 1).saturating_mul(PAGE_SIZE),
+            Pages::Sparse(pages) => (pages.len() + 1) * PAGE_SIZE,
+        };
+        if footprint > self.limit {
+            return false;
+        }
+        let page = Box::new(Page::copy_of(&self.image, index));
+        match &mut self.pages {
+            Pages::Dense(pages) => {
+                if index >= pages.len() {
+                    pages.resize_with(index + 1, || None);
+                }
+                pages[index] = Some(page);
+            }
+            Pages::Sparse(pages) => {
+                pages.insert(index, page);
+            }
+        }
+        true
+    }
+}
+
+impl Pages {
+    #[inline]
+    fn get(&self, index: usize) -> Option<&Page> {
+        match self {
+            Pages::Dense(pages) => pages.get(index)?.as_deref(),
+            Pages::Sparse(pages) => pages.get(&index).map(|page| &**page),
+        }
+    }
+
+    #[inline]
+    fn get_mut(&mut self, index: usize) -> Option<&mut Page> {
+        match self {
+            Pages::Dense(pages) => pages.get_mut(index)?.as_deref_mut(),
+            Pages::Sparse(pages) => pages.get_mut(&index).map(|page| &mut **page),
+        }
+    }
+
+    // The numbers of the pages that have been written to, in order
+    fn indices(&self) -> Vec<usize> {
+        let mut indices = match self {
+            Pages::Dense(pages) => (0..pages.len())
+                .filter(|&index| pages[index].is_some())
+                .collect(),
+            Pages::Sparse(pages) => pages.keys().copied().collect::<Vec<_>>(),
+        };
+        indices.sort_unstable();
+        indices
+    }
+}
+
+impl Page {
+    // Copies a page of the program image
+    fn copy_of(image: &Image, index: usize) -> Self {
+        let mut page = Self {
+            words: [0; PAGE_SIZE],
+            decoded: [None; PAGE_SIZE],
+        };
+        let start = (index << PAGE_BITS).min(image.words.len());
+        let end = (start + PAGE_SIZE).min(image.words.len());
+        page.words[..end - start].copy_from_slice(&image.words[start..end]);
+        page.decoded[..end - start].copy_from_slice(&image.decoded[start..end]);
+        page
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn private_pages(memory: &Memory) -> Vec<usize> {
+        memory.pages.indices()
+    }
+
+    #[test]
+    pub fn test_copy_on_write() {
+        let image = (1..=3 * PAGE_SIZE as i64).collect::<Vec<_>>();
+        let original = Memory::new(&image);
+        let mut fork = original.clone();
+        assert!(Arc::ptr_eq(&original.image, &fork.image));
+
+        assert!(fork.write(PAGE_SIZE + 5, -1));
+        assert_eq!(Vec::<usize>::new(), private_pages(&original));
+        assert_eq!(vec![1], private_pages(&fork));
+        assert_eq!(PAGE_SIZE as i64 + 6, original.read(PAGE_SIZE + 5));
+        assert_eq!(-1, fork.read(PAGE_SIZE + 5));
+        assert_eq!(PAGE_SIZE as i64 + 7, fork.read(PAGE_SIZE + 6));
+        assert_eq!(image, original.to_vec());
+
+        let mut second_fork = fork.clone();
+        assert!(second_fork.write(PAGE_SIZE + 5, -2));
+        assert_eq!(-1, fork.read(PAGE_SIZE + 5));
+        assert_eq!(-2, second_fork.read(PAGE_SIZE + 5));
+    }
+
+    #[test]
+    pub fn test_growth() {
+        let mut memory = Memory::new(&[1, 2, 3]);
+        assert_eq!(0, memory.read(10 * PAGE_SIZE));
+        assert_eq!(3, memory.len());
+        assert!(memory.write(10 * PAGE_SIZE, 7));
+        assert_eq!(7, memory.read(10 * PAGE_SIZE));
+        assert_eq!(10 * PAGE_SIZE + 1, memory.len());
+        assert_eq!(vec![10], private_pages(&memory));
+        assert_eq!(11 * PAGE_SIZE, memory.footprint());
+    }
+
+    #[test]
+    pub fn test_sparse() {
+        let far = 1 << 40;
+        let mut memory = Memory::new(&[1, 2, 3]);
+        memory.set_model(MemoryModel::Sparse);
+        memory.set_limit(2 * PAGE_SIZE);
+        assert!(memory.write(far, 7));
+        assert!(memory.write(far + 1, 8));
+        assert!(memory.write(1, 5));
+        assert!(!memory.write(far * 2, 9));
+        assert_eq!(vec![0, far >> PAGE_BITS], private_pages(&memory));
+        assert_eq!(2 * PAGE_SIZE, memory.footprint());
+        assert_eq!(
+            [1, 5, 3, 7, 8, 0],
+            [0, 1, 2, far, far + 1, far * 2].map(|a| memory.read(a))
+        );
+
+        // Switching models keeps the pages that have been written
+        let mut memory = Memory::new(&[1, 2, 3]);
+        memory.set_model(MemoryModel::Sparse);
+        assert!(memory.write(3 * PAGE_SIZE, 4));
+        memory.set_model(MemoryModel::Dense);
+        assert_eq!(vec![3], private_pages(&memory));
+        assert_eq!([1, 4], [0, 3 * PAGE_SIZE].map(|a| memory.read(a)));
+    }
+
+    #[test]
+    pub fn test_runs() {
+        let far = 1 << 40;
+        let mut memory = Memory::new(&[0, 1, 2, 0, 0]);
+        memory.set_model(MemoryModel::Sparse);
+        assert!(memory.write(far + 3, 7));
+        assert!(memory.write(3, 3));
+        assert!(memory.write(2 * PAGE_SIZE + 1, 4));
+        assert!(memory.write(3 * PAGE_SIZE, 0));
+        assert_eq!(
+            vec![
+                (1, vec![1, 2, 3]),
+                (2 * PAGE_SIZE + 1, vec![4]),
+                (far + 3, vec![7])
+            ],
+            memory.runs()
+        );
+        assert_eq!(Vec::<(usize, Vec<i64>)>::new(), Memory::new(&[0, 0]).runs());
+    }
+
+    #[test]
+    pub fn test_dense_limit() {
+        let mut memory = Memory::new(&[1, 2, 3]);
+        memory.set_limit(4 * PAGE_SIZE);
+        assert!(memory.write(4 * PAGE_SIZE - 1, 1));
+        assert!(!memory.write(4 * PAGE_SIZE, 1));
+        assert!(!memory.write(1 << 40, 1));
+        assert_eq!(4 * PAGE_SIZE, memory.len());
+    }
+
+    #[test]
+    pub fn test_decoded_headers() {
+        let mut memory = Memory::new(&[1101, 1, 2, 0, 99]);
+        let shared = memory.clone();
+        assert_eq!(Ok(99), memory.header(4).map(|h| h.opcode.value()));
+        assert!(memory.write(4, 42));
+        assert!(memory.header(4).is_err());
+        assert_eq!(Ok(99), shared.clone().header(4).map(|h| h.opcode.value()));
+    }
+}
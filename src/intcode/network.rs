@@ -0,0 +1,194 @@
+use super::{Intcode, IoBus, State, VmError};
+
+// A set of programs whose outputs are connected to each other's inputs. Machines run one
+// after the other in the order they were added, each until it halts or runs out of
+// input, so running the same network always gives the same results.
+#[derive(Clone, Default)]
+pub struct Network {
+    machines: Vec<Node>,
+}
+
+#[derive(Clone)]
+struct Node {
+    program: Intcode,
+    input: IoBus,
+    // Where each output is delivered, in order
+    destinations: Vec<usize>,
+    // Every output the machine has produced
+    outputs: Vec<i64>,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Adds a machine running `program`, returning the id used to refer to it
+    pub fn add(&mut self, program: Intcode) -> usize {
+        self.machines.push(Node {
+            program,
+            input: IoBus::default(),
+            destinations: Vec::new(),
+            outputs: Vec::new(),
+        });
+        self.machines.len() - 1
+    }
+
+    // Delivers every output of `from` to the input of `to`, in addition to any other
+    // machines it's connected to. Connecting several machines to one interleaves their
+    // outputs in the order they're produced.
+    pub fn connect(&mut self, from: usize, to: usize) {
+        self.machines[from].destinations.push(to);
+    }
+
+    // Connects each machine to the next
+    pub fn connect_chain(&mut self, machines: &[usize]) {
+        for pair in machines.windows(2) {
+            self.connect(pair[0], pair[1]);
+        }
+    }
+
+    // Connects each machine to the next, and the last one back to the first
+    pub fn connect_ring(&mut self, machines: &[usize]) {
+        self.connect_chain(machines);
+        if let (Some(&first), Some(&last)) = (machines.first(), machines.last()) {
+            self.connect(last, first);
+        }
+    }
+
+    // Queues input for a machine
+    pub fn send(&mut self, machine: usize, values: &[i64]) {
+        for &value in values {
+            self.machines[machine].input.write(value);
+        }
+    }
+
+    pub fn program(&self, machine: usize) -> &Intcode {
+        &self.machines[machine].program
+    }
+
+    // Every output a machine has produced so far, including ones delivered elsewhere
+    pub fn outputs(&self, machine: usize) -> &[i64] {
+        &self.machines[machine].outputs
+    }
+
+    // Runs machines until every one has either halted or is waiting for input that
+    // nobody is going to send
+    pub fn run(&mut self) -> Result<(), VmError> {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for i in 0..self.machines.len() {
+                if self.machines[i].is_runnable() {
+                    self.run_machine(i)?;
+                    progress = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn run_machine(&mut self, i: usize) -> Result<(), VmError> {
+        let machine = &mut self.machines[i];
+        let start = machine.outputs.len();
+        let outputs = &mut machine.outputs;
+        machine
+            .program
+            .execute_with_io(&mut machine.input, |value| outputs.push(value))?;
+        let machine = &self.machines[i];
+        let deliveries = machine.outputs[start..]
+            .iter()
+            .flat_map(|&value| machine.destinations.iter().map(move |&to| (to, value)))
+            .collect::<Vec<_>>();
+        for (to, value) in deliveries {
+            self.machines[to].input.write(value);
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn is_runnable(&self) -> bool {
+        match self.program.state {
+            State::Running => true,
+            State::WaitingForInput(_) => !self.input.values.is_empty(),
+            State::Halted => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Outputs the sum of the two values it reads, then halts
+    fn adder() -> Intcode {
+        let source = "
+                    IN -> [a]
+                    IN -> [b]
+                    ADD [a], [b] -> [a]
+                    OUT [a]
+                    HALT
+            a:      .data 0
+            b:      .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    // Doubles every value it reads, forever
+    fn doubler() -> Intcode {
+        let source = "
+            loop:   IN -> [value]
+                    MUL [value], #2 -> [value]
+                    OUT [value]
+                    JZ #0, #loop
+            value:  .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_fan_out_and_in() {
+        // A doubler feeding two more doublers, whose outputs are added up
+        let mut network = Network::new();
+        let source = network.add(doubler());
+        let left = network.add(doubler());
+        let right = network.add(doubler());
+        let sum = network.add(adder());
+        network.connect(source, left);
+        network.connect(source, right);
+        network.connect(left, sum);
+        network.connect(right, sum);
+        network.send(source, &[5]);
+        network.run().unwrap();
+        assert_eq!(&[10], network.outputs(source));
+        assert_eq!(&[40], network.outputs(sum));
+        assert!(network.program(sum).is_halted());
+        assert!(!network.program(source).is_halted());
+    }
+
+    #[test]
+    pub fn test_ring() {
+        // Passes a counter around the ring until it exceeds 100
+        let source = "
+            loop:   IN -> [value]
+                    ADD [value], #1 -> [value]
+                    OUT [value]
+                    LT [value], #101 -> [flag]
+                    JNZ [flag], #loop
+                    HALT
+            value:  .data 0
+            flag:   .data 0
+        ";
+        let program = Intcode::new(assemble(source).unwrap());
+        let mut network = Network::new();
+        let machines = [network.add(program.clone()), network.add(program)];
+        network.connect_ring(&machines);
+        network.send(machines[0], &[0]);
+        network.run().unwrap();
+        assert_eq!(Some(&101), network.outputs(machines[0]).last());
+        assert_eq!(Some(&102), network.outputs(machines[1]).last());
+        assert!(machines.iter().all(|&i| network.program(i).is_halted()));
+    }
+}
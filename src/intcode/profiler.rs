@@ -0,0 +1,219 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use super::{Intcode, Opcode, TraceEvent, Tracer};
+
+// Counts what a program does as it runs. Pass it as the tracer to `execute_traced`, then
+// print a `report` to see where the time went.
+#[derive(Clone, Default, Debug)]
+pub struct Profile {
+    steps: u64,
+    executions: HashMap<usize, u64>,
+    opcodes: HashMap<Opcode, u64>,
+    reads: HashMap<usize, u64>,
+    writes: HashMap<usize, u64>,
+    inputs: u64,
+    outputs: u64,
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // The number of instructions executed
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // How many times the instruction at `address` was executed
+    pub fn executions(&self, address: usize) -> u64 {
+        self.executions.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn opcode_count(&self, opcode: Opcode) -> u64 {
+        self.opcodes.get(&opcode).copied().unwrap_or(0)
+    }
+
+    // How many times parameters were loaded from `address`. Fetching instructions doesn't
+    // count.
+    pub fn reads(&self, address: usize) -> u64 {
+        self.reads.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn writes(&self, address: usize) -> u64 {
+        self.writes.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn inputs(&self) -> u64 {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> u64 {
+        self.outputs
+    }
+
+    // Summarizes the profile, listing opcodes by how often they ran, then the `top` most
+    // executed instructions as disassembled from `program`, then the `top` most accessed
+    // memory addresses. Pass the program after it ran, so that self-modified code is
+    // listed the way it last executed.
+    pub fn report(&self, program: &Intcode, top: usize) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.steps.max(1) as f64;
+        writeln!(
+            report,
+            "{} steps, {} inputs, {} outputs",
+            self.steps, self.inputs, self.outputs
+        )
+        .unwrap();
+
+        writeln!(report, "\nopcodes:").unwrap();
+        for (opcode, count) in sorted_by_count(&self.opcodes, usize::MAX) {
+            writeln!(
+                report,
+                "{:>12} {:>6.2}%  {}",
+                count,
+                percent(count),
+                opcode.mnemonic()
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nhot spots:").unwrap();
+        for (address, count) in sorted_by_count(&self.executions, top) {
+            let listing = program.disassemble(address, 1);
+            let instruction = match listing.entries().first() {
+                Some(entry) => listing.format_entry(entry),
+                None => format!("{:04}: ?", address),
+            };
+            writeln!(
+                report,
+                "{:>12} {:>6.2}%  {}",
+                count,
+                percent(count),
+                instruction
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nmemory:").unwrap();
+        writeln!(report, "{:>12} {:>12}  address", "reads", "writes").unwrap();
+        let mut accesses = self.reads.clone();
+        for (&address, &count) in &self.writes {
+            *accesses.entry(address).or_insert(0) += count;
+        }
+        for (address, _) in sorted_by_count(&accesses, top) {
+            writeln!(
+                report,
+                "{:>12} {:>12}  {:04}",
+                self.reads(address),
+                self.writes(address),
+                address
+            )
+            .unwrap();
+        }
+        report
+    }
+}
+
+// The `top` entries with the highest counts, breaking ties by key so reports are stable
+fn sorted_by_count<K: Copy + Ord>(counts: &HashMap<K, u64>, top: usize) -> Vec<(K, u64)> {
+    let mut counts = counts
+        .iter()
+        .map(|(&key, &count)| (key, count))
+        .collect::<Vec<_>>();
+    counts.sort_by_key(|&(key, count)| (Reverse(count), key));
+    counts.truncate(top);
+    counts
+}
+
+impl Tracer for Profile {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.steps += 1;
+        *self.executions.entry(event.ip).or_insert(0) += 1;
+        *self.opcodes.entry(event.opcode).or_insert(0) += 1;
+        for address in event.operands.iter().filter_map(|operand| operand.address) {
+            *self.reads.entry(address).or_insert(0) += 1;
+        }
+        for write in &event.writes {
+            *self.writes.entry(write.address).or_insert(0) += 1;
+        }
+        self.inputs += event.inputs.len() as u64;
+        self.outputs += event.outputs.len() as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, Intcode};
+
+    // Sums the numbers below its input
+    fn sum() -> Intcode {
+        let source = "
+                    IN -> [limit]
+            loop:   ADD [total], [counter] -> [total]
+                    ADD [counter], #1 -> [counter]
+                    LT [counter], [limit] -> [flag]
+                    JNZ [flag], #loop
+                    OUT [total]
+                    HALT
+            limit:   .data 0
+            total:   .data 0
+            counter: .data 0
+            flag:    .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_counts() {
+        let mut program = sum();
+        let mut profile = Profile::new();
+        program.execute_traced(|| 10, |_| {}, &mut profile).unwrap();
+        assert_eq!(1 + 4 * 10 + 2, profile.steps());
+        assert_eq!(1, profile.executions(0));
+        assert_eq!(10, profile.executions(2));
+        assert_eq!(0, profile.executions(3));
+        assert_eq!(20, profile.opcode_count(Opcode::Add));
+        assert_eq!(1, profile.opcode_count(Opcode::Halt));
+        assert_eq!(0, profile.opcode_count(Opcode::Multiply));
+        assert_eq!((1, 1), (profile.inputs(), profile.outputs()));
+
+        // limit is written once by IN and read by every LT
+        assert_eq!((10, 1), (profile.reads(20), profile.writes(20)));
+        // counter is read by ADD (twice per iteration) and LT
+        assert_eq!((30, 10), (profile.reads(22), profile.writes(22)));
+    }
+
+    #[test]
+    pub fn test_report() {
+        let mut program = sum();
+        let mut profile = Profile::new();
+        program.execute_traced(|| 10, |_| {}, &mut profile).unwrap();
+        let report = profile.report(&program, 2);
+        assert_eq!(
+            "43 steps, 1 inputs, 1 outputs
+
+opcodes:
+          20  46.51%  ADD
+          10  23.26%  JNZ
+          10  23.26%  LT
+           1   2.33%  IN
+           1   2.33%  OUT
+           1   2.33%  HALT
+
+hot spots:
+          10  23.26%  0002: ADD [21], [22] -> [21]
+          10  23.26%  0006: ADD [22], #1 -> [22]
+
+memory:
+       reads       writes  address
+          30           10  0022
+          11           10  0021
+",
+            report
+        );
+    }
+}
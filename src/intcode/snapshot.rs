@@ -0,0 +1,329 @@
+use std::convert::TryFrom;
+use std::io::{self, ErrorKind, Read, Write};
+
+use super::{Intcode, MemoryModel, Op, Opcode, Parameter, ParameterMode, State, MAX_PARAMETERS};
+
+// Snapshots start with a magic number and a format version, followed by the registers,
+// the state (including any instruction waiting for input), the memory model and limit,
+// and finally memory as runs of words, each with its start address and length, so that
+// far writes to sparse memory don't take up the space in between. Integers are stored
+// as zigzag-encoded LEB128 varints, which keeps typical puzzle programs to a couple of
+// bytes per memory word.
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 2;
+
+const STATE_RUNNING: u8 = 0;
+const STATE_WAITING_FOR_INPUT: u8 = 1;
+const STATE_HALTED: u8 = 2;
+
+const MODEL_DENSE: u8 = 0;
+const MODEL_SPARSE: u8 = 1;
+
+// Stands in for a memory limit too large for a varint, i.e. no limit at all
+const UNLIMITED: i64 = -1;
+
+impl Intcode {
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        write_varint(&mut writer, self.ip as i64)?;
+        write_varint(&mut writer, self.relative_base)?;
+        match &self.state {
+            State::Running => writer.write_all(&[STATE_RUNNING])?,
+            State::Halted => writer.write_all(&[STATE_HALTED])?,
+            State::WaitingForInput(op) => {
+                writer.write_all(&[STATE_WAITING_FOR_INPUT])?;
+                write_varint(&mut writer, op.opcode.value())?;
+                for parameter in op.parameters() {
+                    writer.write_all(&[mode_tag(parameter.mode)])?;
+                    write_varint(&mut writer, parameter.value)?;
+                }
+            }
+        }
+        writer.write_all(&[match self.memory_model() {
+            MemoryModel::Dense => MODEL_DENSE,
+            MemoryModel::Sparse => MODEL_SPARSE,
+        }])?;
+        let limit = self.memory_limit();
+        write_varint(&mut writer, i64::try_from(limit).unwrap_or(UNLIMITED))?;
+        let runs = self.memory.runs();
+        write_varint(&mut writer, runs.len() as i64)?;
+        for (start, words) in &runs {
+            write_varint(&mut writer, *start as i64)?;
+            write_varint(&mut writer, words.len() as i64)?;
+            for &value in words {
+                write_varint(&mut writer, value)?;
+            }
+        }
+        writer.flush()
+    }
+
+    pub fn load_snapshot<R: Read>(mut reader: R) -> io::Result<Intcode> {
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if &header[..4] != MAGIC {
+            return Err(invalid_data("not an Intcode snapshot"));
+        }
+        if header[4] != VERSION {
+            return Err(invalid_data("unsupported snapshot version"));
+        }
+        let ip = read_length(&mut reader)?;
+        let relative_base = read_varint(&mut reader)?;
+        let state = match read_byte(&mut reader)? {
+            STATE_RUNNING => State::Running,
+            STATE_HALTED => State::Halted,
+            STATE_WAITING_FOR_INPUT => {
+                // Only IN waits for input
+                let opcode = match Opcode::from_value(read_varint(&mut reader)?) {
+                    Some(Opcode::Input) => Opcode::Input,
+                    _ => return Err(invalid_data("invalid opcode waiting for input")),
+                };
+                let mut parameters = [Parameter::default(); MAX_PARAMETERS];
+                for parameter in parameters.iter_mut().take(opcode.parameter_count()) {
+                    let mode = match read_byte(&mut reader)? {
+                        0 => ParameterMode::Position,
+                        1 => ParameterMode::Immediate,
+                        2 => ParameterMode::Relative,
+                        _ => return Err(invalid_data("invalid parameter mode")),
+                    };
+                    let value = read_varint(&mut reader)?;
+                    *parameter = Parameter { mode, value };
+                }
+                State::WaitingForInput(Op { opcode, parameters })
+            }
+            _ => return Err(invalid_data("invalid state")),
+        };
+        let model = match read_byte(&mut reader)? {
+            MODEL_DENSE => MemoryModel::Dense,
+            MODEL_SPARSE => MemoryModel::Sparse,
+            _ => return Err(invalid_data("invalid memory model")),
+        };
+        let limit = match read_varint(&mut reader)? {
+            UNLIMITED => usize::MAX,
+            limit if limit < 0 => return Err(invalid_data("negative memory limit")),
+            limit => limit as usize,
+        };
+
+        let mut runs = Vec::new();
+        let mut end = 0;
+        for _ in 0..read_length(&mut reader)? {
+            let start = read_length(&mut reader)?;
+            if !runs.is_empty() && start <= end {
+                return Err(invalid_data("memory runs out of order"));
+            }
+            let len = read_length(&mut reader)?;
+            let mut words = Vec::with_capacity(len.min(1 << 20));
+            for _ in 0..len {
+                words.push(read_varint(&mut reader)?);
+            }
+            end = start
+                .checked_add(len)
+                .ok_or_else(|| invalid_data("memory run is too long"))?;
+            runs.push((start, words));
+        }
+
+        // The run at address 0, if there is one, becomes the image, and the others are
+        // written on top of it, so that a run past the limit is rejected before the VM
+        // tries to make room for it
+        let image = match runs.first() {
+            Some((0, _)) => runs.remove(0).1,
+            _ => Vec::new(),
+        };
+        let mut program = Intcode::new(image)
+            .with_memory_model(model)
+            .with_memory_limit(limit);
+        for (start, words) in runs {
+            for (address, value) in (start..).zip(words) {
+                *program
+                    .mem_access(address as i64)
+                    .map_err(|_| invalid_data("memory run is past the memory limit"))? = value;
+            }
+        }
+        Ok(Intcode {
+            ip,
+            relative_base,
+            state,
+            ..program
+        })
+    }
+}
+
+fn mode_tag(mode: ParameterMode) -> u8 {
+    match mode {
+        ParameterMode::Position => 0,
+        ParameterMode::Immediate => 1,
+        ParameterMode::Relative => 2,
+    }
+}
+
+fn write_varint<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    let mut bytes = [0; 10];
+    let mut len = 0;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+    writer.write_all(&bytes[..len])
+}
+
+fn read_varint<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut zigzag = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = read_byte(reader)?;
+        zigzag |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64));
+        }
+    }
+    Err(invalid_data("varint is too long"))
+}
+
+fn read_length<R: Read>(reader: &mut R) -> io::Result<usize> {
+    let value = read_varint(reader)?;
+    if value < 0 {
+        return Err(invalid_data("negative length"));
+    }
+    Ok(value as usize)
+}
+
+fn read_byte<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut byte = [0];
+    reader.read_exact(&mut byte)?;
+    Ok(byte[0])
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, IoBus, RunOutcome};
+
+    fn echo() -> Intcode {
+        let source = "
+            loop:   IN -> [rb+100]
+                    MUL [rb+100], #-1000 -> [rb+100]
+                    OUT [rb+100]
+                    ARB #1
+                    JZ #0, #loop
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_round_trip() {
+        let mut program = echo();
+        let mut input = IoBus::default();
+        let mut output = IoBus::default();
+        input.write(1);
+        input.write(2);
+        program.execute_with_io(&mut input, &mut output).unwrap();
+
+        let mut snapshot = Vec::new();
+        program.save_snapshot(&mut snapshot).unwrap();
+        let mut restored = Intcode::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(program.ip, restored.ip);
+        assert_eq!(program.relative_base, restored.relative_base);
+        assert_eq!(program.state, restored.state);
+
+        input.write(3);
+        restored.execute_with_io(&mut input, &mut output).unwrap();
+        let outputs = (0..3).filter_map(|_| output.read()).collect::<Vec<_>>();
+        assert_eq!(vec![-1000, -2000, -3000], outputs);
+        assert_eq!(&restored.memory()[100..103], &[-1000, -2000, -3000]);
+    }
+
+    #[test]
+    pub fn test_sparse_round_trip() {
+        // Stores 42 at 10^12 and waits for input
+        let mut program = "21101,0,42,1000000000000,3,0,99"
+            .parse::<Intcode>()
+            .unwrap()
+            .with_memory_model(MemoryModel::Sparse)
+            .with_memory_limit(1 << 10);
+        assert_eq!(
+            Ok(RunOutcome::NeedsInput),
+            program.execute_with_io(IoBus::default(), |_| {})
+        );
+
+        let mut snapshot = Vec::new();
+        program.save_snapshot(&mut snapshot).unwrap();
+        assert!(snapshot.len() < 100);
+        let mut restored = Intcode::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(MemoryModel::Sparse, restored.memory_model());
+        assert_eq!(1 << 10, restored.memory_limit());
+        assert_eq!(Ok(&mut 42), restored.mem_access(1_000_000_000_000));
+        assert_eq!(
+            Ok(RunOutcome::Halted),
+            restored.execute_with_io(|| 7, |_| {})
+        );
+        assert_eq!(Ok(&mut 7), restored.mem_access(0));
+
+        // Dense memory with the default limit round trips too
+        let mut snapshot = Vec::new();
+        echo().save_snapshot(&mut snapshot).unwrap();
+        let restored = Intcode::load_snapshot(snapshot.as_slice()).unwrap();
+        assert_eq!(MemoryModel::Dense, restored.memory_model());
+        assert_eq!(echo().memory_limit(), restored.memory_limit());
+        // Trailing zeros aren't saved
+        assert_eq!(echo().memory()[..11], restored.memory()[..]);
+    }
+
+    #[test]
+    pub fn test_varints() {
+        for &value in &[0, 1, -1, 63, -64, 64, 1 << 40, i64::MAX, i64::MIN] {
+            let mut bytes = Vec::new();
+            write_varint(&mut bytes, value).unwrap();
+            assert_eq!(value, read_varint(&mut bytes.as_slice()).unwrap());
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_snapshots() {
+        let mut snapshot = Vec::new();
+        echo().save_snapshot(&mut snapshot).unwrap();
+        assert!(Intcode::load_snapshot(&snapshot[..snapshot.len() - 1]).is_err());
+        snapshot[0] = b'X';
+        assert_eq!(
+            Some(ErrorKind::InvalidData),
+            Intcode::load_snapshot(snapshot.as_slice())
+                .err()
+                .map(|e| e.kind())
+        );
+
+        let invalid = |snapshot: Vec<u8>| {
+            Intcode::load_snapshot(snapshot.as_slice())
+                .err()
+                .map(|e| e.kind())
+        };
+        // A dense VM limited to 2^24 words with a run at 2^50
+        let mut snapshot = Vec::new();
+        snapshot.extend_from_slice(MAGIC);
+        snapshot.extend_from_slice(&[VERSION, 0, 0, STATE_RUNNING, MODEL_DENSE]);
+        for &value in &[1 << 24, 1, 1 << 50, 1, 7] {
+            write_varint(&mut snapshot, value).unwrap();
+        }
+        assert_eq!(Some(ErrorKind::InvalidData), invalid(snapshot));
+
+        // A VM waiting for input at an ADD
+        let mut snapshot = Vec::new();
+        snapshot.extend_from_slice(MAGIC);
+        snapshot.extend_from_slice(&[VERSION, 0, 0, STATE_WAITING_FOR_INPUT]);
+        write_varint(&mut snapshot, Opcode::Add.value()).unwrap();
+        for _ in 0..Opcode::Add.parameter_count() {
+            snapshot.extend_from_slice(&[0, 0]);
+        }
+        snapshot.extend_from_slice(&[MODEL_DENSE, 0, 0]);
+        assert_eq!(Some(ErrorKind::InvalidData), invalid(snapshot));
+    }
+}
@@ -0,0 +1,697 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::ops::RangeInclusive;
+
+use super::memory::Memory;
+use super::{Budget, Header, Intcode, Opcode, ParameterMode, RunOutcome, VmError};
+
+// A constant plus a sum of named symbols, each multiplied by a coefficient
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct LinearExpr {
+    constant: i64,
+    // Symbols with a coefficient of zero are left out
+    terms: BTreeMap<String, i64>,
+}
+
+// Runs a program with some memory cells and inputs standing for unknown values, tracking
+// what everything the program computes from them is in terms of those symbols. This works
+// for programs that only add the symbols together or multiply them by constants, like
+// day 2. Values that aren't linear in the symbols, such as the product of two symbols,
+// become unknown. Anything that needs a value that depends on the symbols, such as a
+// branch on it or a write to an address computed from it, stops execution with an error
+// so that the caller can fall back to running the program on concrete values.
+#[derive(Clone)]
+pub struct SymbolicIntcode {
+    memory: Memory,
+    // Memory cells whose value depends on a symbol, which override `memory`. Unknown
+    // values are `None`.
+    symbolic: HashMap<usize, Option<LinearExpr>>,
+    ip: usize,
+    relative_base: i64,
+    inputs: VecDeque<LinearExpr>,
+    outputs: Vec<Option<LinearExpr>>,
+    halted: bool,
+}
+
+// Why symbolic execution stopped at the instruction at `ip`, or why working out something
+// about an expression failed. An instruction that fails hasn't run, so the program can be
+// inspected as it was just before.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum SymbolicError {
+    Vm(VmError),
+    // The instruction word itself depends on a symbol
+    SymbolicInstruction { ip: usize },
+    // The address written to, or a relative base offset, depends on a symbol
+    SymbolicAddress { ip: usize },
+    // Whether a jump is taken, or where it goes, depends on a symbol
+    SymbolicBranch { ip: usize },
+    // A constant or coefficient doesn't fit in an i64
+    Overflow,
+}
+
+impl LinearExpr {
+    pub fn constant(value: i64) -> Self {
+        Self {
+            constant: value,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub fn symbol(name: &str) -> Self {
+        let mut terms = BTreeMap::new();
+        terms.insert(name.to_string(), 1);
+        Self { constant: 0, terms }
+    }
+
+    // The value of the expression if it doesn't depend on any symbols
+    pub fn as_constant(&self) -> Option<i64> {
+        if self.terms.is_empty() {
+            Some(self.constant)
+        } else {
+            None
+        }
+    }
+
+    // The part of the expression that doesn't depend on any symbols
+    pub fn constant_term(&self) -> i64 {
+        self.constant
+    }
+
+    pub fn coefficient(&self, name: &str) -> i64 {
+        self.terms.get(name).copied().unwrap_or(0)
+    }
+
+    // The symbols the expression depends on, in alphabetical order
+    pub fn symbols(&self) -> impl Iterator<Item = &str> {
+        self.terms.keys().map(String::as_str)
+    }
+
+    // The value of the expression given the value of each symbol
+    pub fn evaluate<F: Fn(&str) -> i64>(&self, value: F) -> Result<i64, SymbolicError> {
+        self.terms
+            .iter()
+            .try_fold(self.constant, |sum, (name, coefficient)| {
+                sum.checked_add(coefficient.checked_mul(value(name))?)
+            })
+            .ok_or(SymbolicError::Overflow)
+    }
+
+    // Finds values for the given symbols, each within its range, that make the expression
+    // equal `target`. The values are returned in the same order as the symbols, and if
+    // there's more than one solution, the first one found by counting through the ranges
+    // with the last symbol changing fastest is returned. Returns `None` if there's no
+    // solution or the expression depends on a symbol that isn't given, and an error if
+    // the search needs numbers that don't fit in an i64.
+    pub fn solve(
+        &self,
+        target: i64,
+        symbols: &[(&str, RangeInclusive<i64>)],
+    ) -> Result<Option<Vec<i64>>, SymbolicError> {
+        if self
+            .symbols()
+            .any(|name| symbols.iter().all(|&(symbol, _)| symbol != name))
+        {
+            return Ok(None);
+        }
+        let remaining = target
+            .checked_sub(self.constant)
+            .ok_or(SymbolicError::Overflow)?;
+        let mut values = Vec::with_capacity(symbols.len());
+        if self.solve_from(remaining, symbols, &mut values)? {
+            Ok(Some(values))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Searches for values of `symbols` that add up to `remaining`, appending them to
+    // `values`
+    fn solve_from(
+        &self,
+        remaining: i64,
+        symbols: &[(&str, RangeInclusive<i64>)],
+        values: &mut Vec<i64>,
+    ) -> Result<bool, SymbolicError> {
+        let ((name, range), rest) = match symbols.split_first() {
+            Some(first) => first,
+            None => return Ok(remaining == 0),
+        };
+        let coefficient = self.coefficient(name);
+        if rest.is_empty() {
+            // The last value can be worked out directly
+            let value = if coefficient == 0 {
+                if remaining != 0 {
+                    return Ok(false);
+                }
+                *range.start()
+            } else {
+                // Only i64::MIN / -1 overflows, and that's no solution to a range of i64s
+                match remaining.checked_rem(coefficient) {
+                    Some(0) => remaining / coefficient,
+                    _ => return Ok(false),
+                }
+            };
+            if range.contains(&value) {
+                values.push(value);
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        for value in range.clone() {
+            let remaining = coefficient
+                .checked_mul(value)
+                .and_then(|product| remaining.checked_sub(product))
+                .ok_or(SymbolicError::Overflow)?;
+            values.push(value);
+            if self.solve_from(remaining, rest, values)? {
+                return Ok(true);
+            }
+            values.pop();
+        }
+        Ok(false)
+    }
+
+    fn add(&self, other: &LinearExpr) -> Result<LinearExpr, SymbolicError> {
+        let mut sum = self.clone();
+        sum.constant = checked(sum.constant.checked_add(other.constant))?;
+        for (name, &coefficient) in &other.terms {
+            let term = sum.terms.entry(name.clone()).or_insert(0);
+            *term = checked(term.checked_add(coefficient))?;
+        }
+        sum.terms.retain(|_, coefficient| *coefficient != 0);
+        Ok(sum)
+    }
+
+    fn scale(&self, factor: i64) -> Result<LinearExpr, SymbolicError> {
+        let mut terms = BTreeMap::new();
+        for (name, &coefficient) in &self.terms {
+            let coefficient = checked(coefficient.checked_mul(factor))?;
+            if coefficient != 0 {
+                terms.insert(name.clone(), coefficient);
+            }
+        }
+        Ok(LinearExpr {
+            constant: checked(self.constant.checked_mul(factor))?,
+            terms,
+        })
+    }
+
+    // `None` if the product isn't linear
+    fn multiply(&self, other: &LinearExpr) -> Result<Option<LinearExpr>, SymbolicError> {
+        match (self.as_constant(), other.as_constant()) {
+            (Some(factor), _) => other.scale(factor).map(Some),
+            (_, Some(factor)) => self.scale(factor).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    // Compares two expressions with `compare` if their difference is constant, which is
+    // all it takes to know how they compare for every value of the symbols
+    fn compare<F: Fn(i64) -> bool>(
+        &self,
+        other: &LinearExpr,
+        compare: F,
+    ) -> Result<Option<i64>, SymbolicError> {
+        let difference = self.add(&other.scale(-1)?)?.as_constant();
+        Ok(difference.map(|difference| if compare(difference) { 1 } else { 0 }))
+    }
+}
+
+fn checked(value: Option<i64>) -> Result<i64, SymbolicError> {
+    value.ok_or(SymbolicError::Overflow)
+}
+
+impl From<i64> for LinearExpr {
+    fn from(value: i64) -> Self {
+        LinearExpr::constant(value)
+    }
+}
+
+impl Display for LinearExpr {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut first = true;
+        for (name, &coefficient) in &self.terms {
+            let magnitude = if first {
+                if coefficient < 0 {
+                    write!(f, "-")?;
+                }
+                coefficient.unsigned_abs()
+            } else {
+                write!(f, " {} ", if coefficient < 0 { '-' } else { '+' })?;
+                coefficient.unsigned_abs()
+            };
+            if magnitude != 1 {
+                write!(f, "{}*", magnitude)?;
+            }
+            write!(f, "{}", name)?;
+            first = false;
+        }
+        if first {
+            write!(f, "{}", self.constant)
+        } else if self.constant != 0 {
+            let sign = if self.constant < 0 { '-' } else { '+' };
+            write!(f, " {} {}", sign, self.constant.unsigned_abs())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl SymbolicIntcode {
+    // Starts from wherever `program` is. If it's waiting for input, the instruction that
+    // needs it runs again once there's an input.
+    pub fn new(program: &Intcode) -> Self {
+        Self {
+            memory: program.memory.clone(),
+            symbolic: HashMap::new(),
+            ip: program.ip,
+            relative_base: program.relative_base,
+            inputs: VecDeque::new(),
+            outputs: Vec::new(),
+            halted: program.is_halted(),
+        }
+    }
+
+    // Replaces the value at `address` with a symbol
+    pub fn with_symbol(mut self, address: usize, name: &str) -> Self {
+        self.symbolic
+            .insert(address, Some(LinearExpr::symbol(name)));
+        self
+    }
+
+    // Adds an input for the program to read, which can be a constant or an expression
+    // such as `LinearExpr::symbol("x")`
+    pub fn with_input<E: Into<LinearExpr>>(mut self, value: E) -> Self {
+        self.inputs.push_back(value.into());
+        self
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // The value at `address`, or `None` if it isn't a linear expression of the symbols
+    pub fn read(&self, address: usize) -> Option<LinearExpr> {
+        match self.symbolic.get(&address) {
+            Some(value) => value.clone(),
+            None => Some(LinearExpr::constant(self.memory.read(address))),
+        }
+    }
+
+    // Everything the program has output so far, with `None` for values that aren't
+    // linear expressions of the symbols
+    pub fn outputs(&self) -> &[Option<LinearExpr>] {
+        &self.outputs
+    }
+
+    // Runs until the program halts or runs out of inputs
+    pub fn execute(&mut self) -> Result<RunOutcome, SymbolicError> {
+        self.execute_with_budget(&mut Budget::unlimited())
+    }
+
+    // Like `execute`, but gives up once the budget is exhausted
+    pub fn execute_with_budget(
+        &mut self,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, SymbolicError> {
+        loop {
+            if budget.should_stop() {
+                return Ok(RunOutcome::BudgetExhausted);
+            }
+            if !self.step()? {
+                return Ok(if self.halted {
+                    RunOutcome::Halted
+                } else {
+                    RunOutcome::NeedsInput
+                });
+            }
+            budget.steps += 1;
+        }
+    }
+
+    fn step(&mut self) -> Result<bool, SymbolicError> {
+        if self.halted {
+            return Ok(false);
+        }
+        let ip = self.ip;
+        let word = self
+            .constant(ip)
+            .ok_or(SymbolicError::SymbolicInstruction { ip })?;
+        let header = Header::decode(word, ip)?;
+        let mut next_ip = ip + 1 + header.opcode.parameter_count();
+
+        match header.opcode {
+            Opcode::Add => {
+                let value = match (self.load(&header, 0)?, self.load(&header, 1)?) {
+                    (Some(a), Some(b)) => Some(a.add(&b)?),
+                    _ => None,
+                };
+                self.store(&header, 2, value)?;
+            }
+            Opcode::Multiply => {
+                let value = match (self.load(&header, 0)?, self.load(&header, 1)?) {
+                    (Some(a), Some(b)) => a.multiply(&b)?,
+                    _ => None,
+                };
+                self.store(&header, 2, value)?;
+            }
+            Opcode::Input => match self.inputs.pop_front() {
+                Some(value) => self.store(&header, 0, Some(value))?,
+                None => return Ok(false),
+            },
+            Opcode::Output => {
+                let value = self.load(&header, 0)?;
+                self.outputs.push(value);
+            }
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                let condition = self
+                    .load_constant(&header, 0)?
+                    .ok_or(SymbolicError::SymbolicBranch { ip })?;
+                if (condition != 0) == (header.opcode == Opcode::JumpIfTrue) {
+                    let target = self
+                        .load_constant(&header, 1)?
+                        .ok_or(SymbolicError::SymbolicBranch { ip })?;
+                    if target < 0 {
+                        return Err(VmError::NegativeJump { target, ip }.into());
+                    }
+                    next_ip = target as usize;
+                }
+            }
+            Opcode::LessThan | Opcode::Equals => {
+                let value = match (self.load(&header, 0)?, self.load(&header, 1)?) {
+                    (Some(a), Some(b)) if header.opcode == Opcode::LessThan => {
+                        a.compare(&b, |difference| difference < 0)?
+                    }
+                    (Some(a), Some(b)) => a.compare(&b, |difference| difference == 0)?,
+                    _ => None,
+                };
+                self.store(&header, 2, value.map(LinearExpr::constant))?;
+            }
+            Opcode::RelativeBaseOffset => {
+                self.relative_base += self
+                    .load_constant(&header, 0)?
+                    .ok_or(SymbolicError::SymbolicAddress { ip })?;
+            }
+            Opcode::Halt => {
+                self.halted = true;
+                return Ok(false);
+            }
+            Opcode::Extended(_) => unreachable!("only built-in opcodes are decoded"),
+        }
+        self.ip = next_ip;
+        Ok(true)
+    }
+
+    fn constant(&self, address: usize) -> Option<i64> {
+        self.read(address)?.as_constant()
+    }
+
+    // The address parameter `i` of the current instruction refers to. That's `None` for
+    // immediate parameters, and an error if the address depends on a symbol.
+    fn address(&self, header: &Header, i: usize) -> Result<Option<usize>, SymbolicError> {
+        let ip = self.ip;
+        let base = match header.modes[i] {
+            ParameterMode::Position => 0,
+            ParameterMode::Relative => self.relative_base,
+            ParameterMode::Immediate => return Ok(None),
+        };
+        let value = self
+            .constant(ip + 1 + i)
+            .ok_or(SymbolicError::SymbolicAddress { ip })?;
+        let address = base + value;
+        if address < 0 {
+            return Err(VmError::NegativeAddress { address, ip }.into());
+        }
+        Ok(Some(address as usize))
+    }
+
+    // Loading from an address that depends on a symbol gives an unknown value rather
+    // than an error, since it only matters if the value is used for something
+    fn load(&self, header: &Header, i: usize) -> Result<Option<LinearExpr>, SymbolicError> {
+        match self.address(header, i) {
+            Ok(Some(address)) => Ok(self.read(address)),
+            Ok(None) => Ok(self.read(self.ip + 1 + i)),
+            Err(SymbolicError::SymbolicAddress { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn load_constant(&self, header: &Header, i: usize) -> Result<Option<i64>, SymbolicError> {
+        Ok(self.load(header, i)?.and_then(|value| value.as_constant()))
+    }
+
+    // Writing to an address that depends on a symbol is an error though, since any
+    // address could be overwritten
+    fn destination(&self, header: &Header, i: usize) -> Result<usize, SymbolicError> {
+        match self.address(header, i)? {
+            Some(address) => Ok(address),
+            None => Err(VmError::WriteToImmediate { ip: self.ip }.into()),
+        }
+    }
+
+    fn store(
+        &mut self,
+        header: &Header,
+        i: usize,
+        value: Option<LinearExpr>,
+    ) -> Result<(), SymbolicError> {
+        let address = self.destination(header, i)?;
+        match value.as_ref().and_then(LinearExpr::as_constant) {
+            Some(value) => {
+                self.symbolic.remove(&address);
+                if !self.memory.write(address, value) {
+                    let ip = self.ip;
+                    return Err(VmError::MemoryLimitExceeded { address, ip }.into());
+                }
+            }
+            None => {
+                self.symbolic.insert(address, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Display for SymbolicError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            SymbolicError::Vm(e) => write!(f, "{}", e),
+            SymbolicError::SymbolicInstruction { ip } => {
+                write!(f, "the instruction at ip {} depends on a symbol", ip)
+            }
+            SymbolicError::SymbolicAddress { ip } => {
+                write!(f, "an address at ip {} depends on a symbol", ip)
+            }
+            SymbolicError::SymbolicBranch { ip } => {
+                write!(f, "the jump at ip {} depends on a symbol", ip)
+            }
+            SymbolicError::Overflow => write!(f, "an expression overflowed"),
+        }
+    }
+}
+
+impl Error for SymbolicError {}
+
+impl From<VmError> for SymbolicError {
+    fn from(e: VmError) -> Self {
+        SymbolicError::Vm(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+
+    // Works out 3 * (x + 4) + 2 * y - x, so 2x + 2y + 12
+    fn straight_line() -> Intcode {
+        let source = "
+                    ADD [x], #4 -> [result]
+                    MUL [result], #3 -> [result]
+                    MUL [y], #2 -> [y]
+                    ADD [result], [y] -> [result]
+                    MUL [x], #-1 -> [x]
+                    ADD [result], [x] -> [result]
+                    OUT [result]
+                    HALT
+            x:      .data 0
+            y:      .data 0
+            result: .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_straight_line() {
+        let mut program = SymbolicIntcode::new(&straight_line())
+            .with_symbol(27, "x")
+            .with_symbol(28, "y");
+        assert_eq!(Ok(RunOutcome::Halted), program.execute());
+        let result = program.read(29).unwrap();
+        assert_eq!("2*x + 2*y + 12", result.to_string());
+        assert_eq!(&[Some(result.clone())], program.outputs());
+        assert_eq!(
+            (2, 2, 12),
+            (
+                result.coefficient("x"),
+                result.coefficient("y"),
+                result.constant_term()
+            )
+        );
+        assert_eq!(
+            Ok(22),
+            result.evaluate(|name| if name == "x" { 2 } else { 3 })
+        );
+        assert_eq!("-x", program.read(27).unwrap().to_string());
+
+        // 2x + 2y + 12 = 30 first holds for x = 0, y = 9
+        assert_eq!(
+            Ok(Some(vec![0, 9])),
+            result.solve(30, &[("x", 0..=99), ("y", 0..=99)])
+        );
+        assert_eq!(
+            Ok(Some(vec![5, 4])),
+            result.solve(30, &[("x", 5..=99), ("y", 0..=99)])
+        );
+        assert_eq!(Ok(None), result.solve(31, &[("x", 0..=99), ("y", 0..=99)]));
+        assert_eq!(Ok(None), result.solve(30, &[("x", 0..=99)]));
+    }
+
+    #[test]
+    pub fn test_overflow() {
+        // x * 2^62 * 4 doesn't fit, even though the program would be fine with x = 0
+        let source = "
+                    MUL [x], #4611686018427387904 -> [x]
+                    MUL [x], #4 -> [x]
+                    HALT
+            x:      .data 0
+        ";
+        assert_eq!(Err(SymbolicError::Overflow), run_with_symbol(source, 9));
+
+        let big = LinearExpr::symbol("x").scale(i64::MAX).unwrap();
+        assert_eq!("9223372036854775807*x", big.to_string());
+        assert_eq!(Err(SymbolicError::Overflow), big.evaluate(|_| 2));
+        assert_eq!(Err(SymbolicError::Overflow), big.add(&big));
+        assert_eq!(
+            Err(SymbolicError::Overflow),
+            big.solve(1, &[("x", 0..=9), ("y", 0..=9)])
+        );
+        assert_eq!(
+            Err(SymbolicError::Overflow),
+            LinearExpr::constant(i64::MIN).solve(1, &[])
+        );
+        assert_eq!(
+            "-9223372036854775808",
+            LinearExpr::constant(i64::MIN).to_string()
+        );
+    }
+
+    #[test]
+    pub fn test_inputs_and_comparisons() {
+        // Comparing x with x + 1 doesn't depend on x
+        let source = "
+                    IN -> [x]
+                    ADD [x], #1 -> [y]
+                    LT [x], [y] -> [flag]
+                    JZ [flag], #0
+                    OUT [y]
+                    IN -> [x]
+                    HALT
+            x:      .data 0
+            y:      .data 0
+            flag:   .data 0
+        ";
+        let program = Intcode::new(assemble(source).unwrap());
+        let mut symbolic = SymbolicIntcode::new(&program).with_input(LinearExpr::symbol("n"));
+        assert_eq!(Ok(RunOutcome::NeedsInput), symbolic.execute());
+        assert_eq!(15, symbolic.ip());
+        assert_eq!(
+            vec!["n + 1".to_string()],
+            symbolic
+                .outputs()
+                .iter()
+                .map(|output| output.as_ref().unwrap().to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(LinearExpr::constant(1)), symbolic.read(20));
+
+        let mut symbolic = symbolic.with_input(-5);
+        assert_eq!(Ok(RunOutcome::Halted), symbolic.execute());
+        assert!(symbolic.is_halted());
+        assert_eq!(Some(-5), symbolic.constant(18));
+    }
+
+    #[test]
+    pub fn test_unknown_values() {
+        // Like day 2, the first instruction reads from addresses that are symbols, but
+        // its result is overwritten before it's used
+        let source = "
+                    ADD [0], [0] -> [result]
+                    MUL [x], [x] -> [square]
+                    ADD [x], #1 -> [result]
+                    OUT [square]
+                    HALT
+            x:      .data 0
+            square: .data 0
+            result: .data 0
+        ";
+        let program = Intcode::new(assemble(source).unwrap());
+        let mut symbolic = SymbolicIntcode::new(&program)
+            .with_symbol(1, "noun")
+            .with_symbol(2, "verb")
+            .with_symbol(15, "x");
+        assert_eq!(Ok(RunOutcome::Halted), symbolic.execute());
+        assert_eq!("x + 1", symbolic.read(17).unwrap().to_string());
+        assert_eq!(None, symbolic.read(16));
+        assert_eq!(&[None], symbolic.outputs());
+    }
+
+    // Runs a program with the value at `x` replaced by a symbol
+    fn run_with_symbol(source: &str, x: usize) -> Result<RunOutcome, SymbolicError> {
+        let program = Intcode::new(assemble(source).unwrap());
+        SymbolicIntcode::new(&program).with_symbol(x, "x").execute()
+    }
+
+    #[test]
+    pub fn test_fallback() {
+        let branch = "
+                    JNZ [x], #3
+                    HALT
+            x:      .data 0
+        ";
+        assert_eq!(
+            Err(SymbolicError::SymbolicBranch { ip: 0 }),
+            run_with_symbol(branch, 4)
+        );
+
+        let pointer = "
+                    ADD [x], #0 -> [target+3]
+            target: ADD #1, #1 -> [0]
+                    HALT
+            x:      .data 0
+        ";
+        assert_eq!(
+            Err(SymbolicError::SymbolicAddress { ip: 4 }),
+            run_with_symbol(pointer, 9)
+        );
+
+        let self_modifying = "
+                    ADD [x], #0 -> [target]
+            target: HALT
+            x:      .data 0
+        ";
+        assert_eq!(
+            Err(SymbolicError::SymbolicInstruction { ip: 4 }),
+            run_with_symbol(self_modifying, 5)
+        );
+    }
+}
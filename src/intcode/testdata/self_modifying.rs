@@ -0,0 +1,278 @@
+// Generated by `intcode-transpile` from a 21-word Intcode image. Do not edit.
+//
+// Instructions found in the image are compiled into the `match` in `resume`. Each one
+// checks that its opcode and parameter modes haven't been overwritten before it runs,
+// and anything else is left to `step`, which interprets one instruction at a time.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::intcode::{Input, Output, RunOutcome, VmError};
+
+// Writes from this address on are kept in a map rather than growing memory
+const FAR_MEMORY: usize = 1 << 24;
+
+#[rustfmt::skip]
+const IMAGE: [i64; 21] = [
+    104, 1, 1001, 1, 1, 1, 1008, 1, 3, 20, 1006, 20,
+    0, 1101, 99, 0, 0, 1106, 0, 0, 0,
+];
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    ip: usize,
+    relative_base: i64,
+    halted: bool,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::with_memory(IMAGE.to_vec())
+    }
+
+    // Runs the compiled code on a different image, such as a patched copy of the original
+    pub fn with_memory(memory: Vec<i64>) -> Self {
+        Self {
+            memory,
+            far: HashMap::new(),
+            ip: 0,
+            relative_base: 0,
+            halted: false,
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Memory up to the highest address written below `FAR_MEMORY`
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(&value) => value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<RunOutcome, VmError> {
+        self.execute_with_io(|| 0, |_| {})
+    }
+
+    // Runs until the program halts or needs input that isn't available
+    pub fn execute_with_io<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut output, false)
+    }
+
+    // Runs until the program produces an output, halts or needs input that isn't available
+    pub fn run<I: Input>(&mut self, mut input: I) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut |_| {}, true)
+    }
+
+    fn resume<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        stop_on_output: bool,
+    ) -> Result<RunOutcome, VmError> {
+        if self.halted {
+            return Ok(RunOutcome::Halted);
+        }
+        loop {
+            match self.ip {
+                0 if self.read(0) == 104 => {
+                    let value = self.read(1);
+                    output.receive_output(value);
+                    self.ip = 2;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                2 if self.read(2) == 1001 => {
+                    let a = self.read(self.position(self.read(3))?);
+                    let b = self.read(4);
+                    let address = self.position(self.read(5))?;
+                    self.store(address, a + b);
+                    self.ip = 6;
+                }
+                6 if self.read(6) == 1008 => {
+                    let a = self.read(self.position(self.read(7))?);
+                    let b = self.read(8);
+                    let address = self.position(self.read(9))?;
+                    self.store(address, i64::from(a == b));
+                    self.ip = 10;
+                }
+                10 if self.read(10) == 1006 => {
+                    if self.read(self.position(self.read(11))?) == 0 {
+                        self.ip = self.jump(self.read(12))?;
+                    } else {
+                        self.ip = 13;
+                    }
+                }
+                13 if self.read(13) == 1101 => {
+                    let a = self.read(14);
+                    let b = self.read(15);
+                    let address = self.position(self.read(16))?;
+                    self.store(address, a + b);
+                    self.ip = 17;
+                }
+                17 if self.read(17) == 1106 => {
+                    if self.read(18) == 0 {
+                        self.ip = self.jump(self.read(19))?;
+                    } else {
+                        self.ip = 20;
+                    }
+                }
+                _ => match self.step(input, output)? {
+                    Some(RunOutcome::Output(_)) if !stop_on_output => {}
+                    Some(outcome) => return Ok(outcome),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    // Interprets the instruction at `ip`, returning why the program stopped or the
+    // value it output, if any
+    fn step<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Option<RunOutcome>, VmError> {
+        let ip = self.ip;
+        let header = self.read(ip);
+        let opcode = header % 100;
+        let parameters = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(VmError::UnknownOpcode { opcode: header, ip }),
+        };
+        let mut modes = [0; 3];
+        let mut digits = header / 100;
+        for mode in modes.iter_mut().take(parameters) {
+            *mode = digits % 10;
+            if !(0..=2).contains(mode) {
+                return Err(VmError::InvalidParameterMode { mode: *mode, ip });
+            }
+            digits /= 10;
+        }
+
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let a = self.operand(modes[0], ip + 1)?;
+                let b = self.operand(modes[1], ip + 2)?;
+                let address = self.address(modes[2], ip + 3)?;
+                let value = match opcode {
+                    1 => a + b,
+                    2 => a * b,
+                    7 => i64::from(a < b),
+                    _ => i64::from(a == b),
+                };
+                self.store(address, value);
+            }
+            3 => {
+                let value = match input.get_input() {
+                    Some(value) => value,
+                    None => return Ok(Some(RunOutcome::NeedsInput)),
+                };
+                let address = self.address(modes[0], ip + 1)?;
+                self.store(address, value);
+            }
+            4 => {
+                let value = self.operand(modes[0], ip + 1)?;
+                output.receive_output(value);
+                self.ip = ip + 2;
+                return Ok(Some(RunOutcome::Output(value)));
+            }
+            5 | 6 => {
+                if (self.operand(modes[0], ip + 1)? != 0) == (opcode == 5) {
+                    self.ip = self.jump(self.operand(modes[1], ip + 2)?)?;
+                    return Ok(None);
+                }
+            }
+            9 => self.relative_base += self.operand(modes[0], ip + 1)?,
+            _ => {
+                self.halted = true;
+                return Ok(Some(RunOutcome::Halted));
+            }
+        }
+        self.ip = ip + 1 + parameters;
+        Ok(None)
+    }
+
+    fn operand(&self, mode: i64, word: usize) -> Result<i64, VmError> {
+        let value = self.read(word);
+        match mode {
+            0 => Ok(self.read(self.position(value)?)),
+            1 => Ok(value),
+            _ => Ok(self.read(self.relative(value)?)),
+        }
+    }
+
+    fn address(&self, mode: i64, word: usize) -> Result<usize, VmError> {
+        match mode {
+            0 => self.position(self.read(word)),
+            1 => Err(VmError::WriteToImmediate { ip: self.ip }),
+            _ => self.relative(self.read(word)),
+        }
+    }
+
+    fn position(&self, address: i64) -> Result<usize, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
+            });
+        }
+        Ok(address as usize)
+    }
+
+    fn relative(&self, offset: i64) -> Result<usize, VmError> {
+        self.position(self.relative_base + offset)
+    }
+
+    fn jump(&self, target: i64) -> Result<usize, VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.ip,
+            });
+        }
+        Ok(target as usize)
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        if address < self.memory.len() {
+            self.memory[address] = value;
+        } else if address < FAR_MEMORY {
+            self.memory.resize(address + 1, 0);
+            self.memory[address] = value;
+        } else {
+            self.far.insert(address, value);
+        }
+    }
+}
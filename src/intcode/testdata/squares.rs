@@ -0,0 +1,345 @@
+// Generated by `intcode-transpile` from a 52-word Intcode image. Do not edit.
+//
+// Instructions found in the image are compiled into the `match` in `resume`. Each one
+// checks that its opcode and parameter modes haven't been overwritten before it runs,
+// and anything else is left to `step`, which interprets one instruction at a time.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use crate::intcode::{Input, Output, RunOutcome, VmError};
+
+// Writes from this address on are kept in a map rather than growing memory
+const FAR_MEMORY: usize = 1 << 24;
+
+#[rustfmt::skip]
+const IMAGE: [i64; 52] = [
+    3, 48, 1006, 48, 47, 109, 51, 1101, 0, 0, 49, 20002,
+    48, 48, 0, 2001, 49, 0, 49, 109, 1, 1001, 48, -1,
+    48, 107, 0, 48, 50, 1005, 50, 11, 4, 49, 107, 100,
+    49, 50, 1008, 50, 1, 50, 4, 50, 1105, 1, 0, 99,
+    0, 0, 0, 0,
+];
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    ip: usize,
+    relative_base: i64,
+    halted: bool,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::with_memory(IMAGE.to_vec())
+    }
+
+    // Runs the compiled code on a different image, such as a patched copy of the original
+    pub fn with_memory(memory: Vec<i64>) -> Self {
+        Self {
+            memory,
+            far: HashMap::new(),
+            ip: 0,
+            relative_base: 0,
+            halted: false,
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Memory up to the highest address written below `FAR_MEMORY`
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(&value) => value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<RunOutcome, VmError> {
+        self.execute_with_io(|| 0, |_| {})
+    }
+
+    // Runs until the program halts or needs input that isn't available
+    pub fn execute_with_io<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut output, false)
+    }
+
+    // Runs until the program produces an output, halts or needs input that isn't available
+    pub fn run<I: Input>(&mut self, mut input: I) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut |_| {}, true)
+    }
+
+    fn resume<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        stop_on_output: bool,
+    ) -> Result<RunOutcome, VmError> {
+        if self.halted {
+            return Ok(RunOutcome::Halted);
+        }
+        loop {
+            match self.ip {
+                0 if self.read(0) == 3 => {
+                    let value = match input.get_input() {
+                        Some(value) => value,
+                        None => return Ok(RunOutcome::NeedsInput),
+                    };
+                    let address = self.position(self.read(1))?;
+                    self.store(address, value);
+                    self.ip = 2;
+                }
+                2 if self.read(2) == 1006 => {
+                    if self.read(self.position(self.read(3))?) == 0 {
+                        self.ip = self.jump(self.read(4))?;
+                    } else {
+                        self.ip = 5;
+                    }
+                }
+                5 if self.read(5) == 109 => {
+                    self.relative_base += self.read(6);
+                    self.ip = 7;
+                }
+                7 if self.read(7) == 1101 => {
+                    let a = self.read(8);
+                    let b = self.read(9);
+                    let address = self.position(self.read(10))?;
+                    self.store(address, a + b);
+                    self.ip = 11;
+                }
+                11 if self.read(11) == 20002 => {
+                    let a = self.read(self.position(self.read(12))?);
+                    let b = self.read(self.position(self.read(13))?);
+                    let address = self.relative(self.read(14))?;
+                    self.store(address, a * b);
+                    self.ip = 15;
+                }
+                15 if self.read(15) == 2001 => {
+                    let a = self.read(self.position(self.read(16))?);
+                    let b = self.read(self.relative(self.read(17))?);
+                    let address = self.position(self.read(18))?;
+                    self.store(address, a + b);
+                    self.ip = 19;
+                }
+                19 if self.read(19) == 109 => {
+                    self.relative_base += self.read(20);
+                    self.ip = 21;
+                }
+                21 if self.read(21) == 1001 => {
+                    let a = self.read(self.position(self.read(22))?);
+                    let b = self.read(23);
+                    let address = self.position(self.read(24))?;
+                    self.store(address, a + b);
+                    self.ip = 25;
+                }
+                25 if self.read(25) == 107 => {
+                    let a = self.read(26);
+                    let b = self.read(self.position(self.read(27))?);
+                    let address = self.position(self.read(28))?;
+                    self.store(address, i64::from(a < b));
+                    self.ip = 29;
+                }
+                29 if self.read(29) == 1005 => {
+                    if self.read(self.position(self.read(30))?) != 0 {
+                        self.ip = self.jump(self.read(31))?;
+                    } else {
+                        self.ip = 32;
+                    }
+                }
+                32 if self.read(32) == 4 => {
+                    let value = self.read(self.position(self.read(33))?);
+                    output.receive_output(value);
+                    self.ip = 34;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                34 if self.read(34) == 107 => {
+                    let a = self.read(35);
+                    let b = self.read(self.position(self.read(36))?);
+                    let address = self.position(self.read(37))?;
+                    self.store(address, i64::from(a < b));
+                    self.ip = 38;
+                }
+                38 if self.read(38) == 1008 => {
+                    let a = self.read(self.position(self.read(39))?);
+                    let b = self.read(40);
+                    let address = self.position(self.read(41))?;
+                    self.store(address, i64::from(a == b));
+                    self.ip = 42;
+                }
+                42 if self.read(42) == 4 => {
+                    let value = self.read(self.position(self.read(43))?);
+                    output.receive_output(value);
+                    self.ip = 44;
+                    if stop_on_output {
+                        return Ok(RunOutcome::Output(value));
+                    }
+                }
+                44 if self.read(44) == 1105 => {
+                    if self.read(45) != 0 {
+                        self.ip = self.jump(self.read(46))?;
+                    } else {
+                        self.ip = 47;
+                    }
+                }
+                47 if self.read(47) == 99 => {
+                    self.halted = true;
+                    return Ok(RunOutcome::Halted);
+                }
+                _ => match self.step(input, output)? {
+                    Some(RunOutcome::Output(_)) if !stop_on_output => {}
+                    Some(outcome) => return Ok(outcome),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    // Interprets the instruction at `ip`, returning why the program stopped or the
+    // value it output, if any
+    fn step<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Option<RunOutcome>, VmError> {
+        let ip = self.ip;
+        let header = self.read(ip);
+        let opcode = header % 100;
+        let parameters = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(VmError::UnknownOpcode { opcode: header, ip }),
+        };
+        let mut modes = [0; 3];
+        let mut digits = header / 100;
+        for mode in modes.iter_mut().take(parameters) {
+            *mode = digits % 10;
+            if !(0..=2).contains(mode) {
+                return Err(VmError::InvalidParameterMode { mode: *mode, ip });
+            }
+            digits /= 10;
+        }
+
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let a = self.operand(modes[0], ip + 1)?;
+                let b = self.operand(modes[1], ip + 2)?;
+                let address = self.address(modes[2], ip + 3)?;
+                let value = match opcode {
+                    1 => a + b,
+                    2 => a * b,
+                    7 => i64::from(a < b),
+                    _ => i64::from(a == b),
+                };
+                self.store(address, value);
+            }
+            3 => {
+                let value = match input.get_input() {
+                    Some(value) => value,
+                    None => return Ok(Some(RunOutcome::NeedsInput)),
+                };
+                let address = self.address(modes[0], ip + 1)?;
+                self.store(address, value);
+            }
+            4 => {
+                let value = self.operand(modes[0], ip + 1)?;
+                output.receive_output(value);
+                self.ip = ip + 2;
+                return Ok(Some(RunOutcome::Output(value)));
+            }
+            5 | 6 => {
+                if (self.operand(modes[0], ip + 1)? != 0) == (opcode == 5) {
+                    self.ip = self.jump(self.operand(modes[1], ip + 2)?)?;
+                    return Ok(None);
+                }
+            }
+            9 => self.relative_base += self.operand(modes[0], ip + 1)?,
+            _ => {
+                self.halted = true;
+                return Ok(Some(RunOutcome::Halted));
+            }
+        }
+        self.ip = ip + 1 + parameters;
+        Ok(None)
+    }
+
+    fn operand(&self, mode: i64, word: usize) -> Result<i64, VmError> {
+        let value = self.read(word);
+        match mode {
+            0 => Ok(self.read(self.position(value)?)),
+            1 => Ok(value),
+            _ => Ok(self.read(self.relative(value)?)),
+        }
+    }
+
+    fn address(&self, mode: i64, word: usize) -> Result<usize, VmError> {
+        match mode {
+            0 => self.position(self.read(word)),
+            1 => Err(VmError::WriteToImmediate { ip: self.ip }),
+            _ => self.relative(self.read(word)),
+        }
+    }
+
+    fn position(&self, address: i64) -> Result<usize, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
+            });
+        }
+        Ok(address as usize)
+    }
+
+    fn relative(&self, offset: i64) -> Result<usize, VmError> {
+        self.position(self.relative_base + offset)
+    }
+
+    fn jump(&self, target: i64) -> Result<usize, VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.ip,
+            });
+        }
+        Ok(target as usize)
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        if address < self.memory.len() {
+            self.memory[address] = value;
+        } else if address < FAR_MEMORY {
+            self.memory.resize(address + 1, 0);
+            self.memory[address] = value;
+        } else {
+            self.far.insert(address, value);
+        }
+    }
+}
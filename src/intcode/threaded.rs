@@ -0,0 +1,171 @@
+use std::panic;
+use std::sync::mpsc::{Receiver, Sender, SyncSender};
+use std::thread::{self, JoinHandle};
+
+use super::{Input, Intcode, Output, RunOutcome, VmError};
+
+// A program running on a thread of its own, see `Intcode::spawn`
+pub struct Machine<I, O> {
+    handle: JoinHandle<Stopped<I, O>>,
+}
+
+// A program that has stopped running on its thread, handed back along with its input and
+// output, whether it stopped normally or failed
+pub struct Stopped<I, O> {
+    pub program: Intcode,
+    pub input: I,
+    pub output: O,
+    pub outcome: Result<RunOutcome, VmError>,
+}
+
+// Reads input from a channel without waiting for it, so a program that runs out of input
+// stops with `RunOutcome::NeedsInput` and can be resumed later. Reading from the bare
+// `Receiver` instead blocks until input arrives, or every sender has been dropped.
+pub struct NonBlocking(pub Receiver<i64>);
+
+impl Intcode {
+    // Runs the program on a new thread until it halts or runs out of input. Machines
+    // connected by channels can be combined into any topology and run concurrently.
+    pub fn spawn<I, O>(mut self, mut input: I, mut output: O) -> Machine<I, O>
+    where
+        I: Input + Send + 'static,
+        O: Output + Send + 'static,
+    {
+        let handle = thread::spawn(move || {
+            // Like `execute_with_io`, but keeps hold of the input and output to hand back
+            let mut execute = || {
+                while self.step_traced(&mut input, &mut output, &mut ())? {}
+                Ok(self.stopped())
+            };
+            let outcome = execute();
+            Stopped {
+                program: self,
+                input,
+                output,
+                outcome,
+            }
+        });
+        Machine { handle }
+    }
+}
+
+impl<I, O> Machine<I, O> {
+    // Waits for the program to stop
+    pub fn join(self) -> Stopped<I, O> {
+        self.handle
+            .join()
+            .unwrap_or_else(|payload| panic::resume_unwind(payload))
+    }
+}
+
+impl<I, O> Stopped<I, O>
+where
+    I: Input + Send + 'static,
+    O: Output + Send + 'static,
+{
+    // Runs the program on a new thread again, with the same input and output, e.g. once
+    // more input has been sent to a program that needed it
+    pub fn resume(self) -> Machine<I, O> {
+        self.program.spawn(self.input, self.output)
+    }
+}
+
+impl Input for Receiver<i64> {
+    fn get_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+impl Input for NonBlocking {
+    fn get_input(&mut self) -> Option<i64> {
+        self.0.try_recv().ok()
+    }
+}
+
+// Outputs sent after the receiver has gone away are dropped, since nobody is listening
+impl Output for Sender<i64> {
+    fn receive_output(&mut self, output: i64) {
+        let _ = self.send(output);
+    }
+}
+
+impl Output for SyncSender<i64> {
+    fn receive_output(&mut self, output: i64) {
+        let _ = self.send(output);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::assemble;
+    use std::sync::mpsc;
+
+    // Adds one to every value it reads, and halts after the value it reads exceeds 100
+    fn increment() -> Intcode {
+        let source = "
+            loop:   IN -> [value]
+                    ADD [value], #1 -> [value]
+                    OUT [value]
+                    LT [value], #101 -> [flag]
+                    JNZ [flag], #loop
+                    HALT
+            value:  .data 0
+            flag:   .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_ring() {
+        // Three machines in a ring, with the last one also reporting to the test
+        let (to_a, a_input) = mpsc::channel();
+        let (to_b, b_input) = mpsc::channel();
+        let (to_c, c_input) = mpsc::channel();
+        let (to_test, results) = mpsc::channel();
+        to_a.send(0).unwrap();
+        let a = increment().spawn(a_input, to_b);
+        let b = increment().spawn(b_input, to_c);
+        let c = increment().spawn(c_input, move |value| {
+            let _ = to_a.send(value);
+            let _ = to_test.send(value);
+        });
+        let outcomes = vec![a.join().outcome, b.join().outcome, c.join().outcome];
+        assert_eq!(vec![Ok(RunOutcome::Halted); 3], outcomes);
+        assert_eq!(Some(102), results.iter().last());
+    }
+
+    #[test]
+    pub fn test_non_blocking() {
+        let (sender, receiver) = mpsc::channel();
+        let (output, outputs) = mpsc::channel();
+        sender.send(41).unwrap();
+        let stopped = increment().spawn(NonBlocking(receiver), output).join();
+        assert_eq!(Ok(RunOutcome::NeedsInput), stopped.outcome);
+        assert_eq!(vec![42], outputs.try_iter().collect::<Vec<_>>());
+        assert_eq!(0, stopped.program.ip());
+
+        // The program carries on reading from the same channel
+        sender.send(100).unwrap();
+        let stopped = stopped.resume().join();
+        assert_eq!(Ok(RunOutcome::Halted), stopped.outcome);
+        assert_eq!(vec![101], outputs.try_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    pub fn test_error() {
+        // Reads a value, then fails trying to output from a negative address
+        let (sender, receiver) = mpsc::channel();
+        let (output, _outputs) = mpsc::channel();
+        sender.send(1).unwrap();
+        sender.send(2).unwrap();
+        let program = "3,0,4,-1,99".parse::<Intcode>().unwrap();
+        let stopped = program.spawn(NonBlocking(receiver), output).join();
+        assert_eq!(
+            Err(VmError::NegativeAddress { address: -1, ip: 2 }),
+            stopped.outcome
+        );
+        assert_eq!(2, stopped.program.ip());
+        assert_eq!(Ok(2), stopped.input.0.try_recv());
+    }
+}
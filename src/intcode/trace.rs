@@ -0,0 +1,222 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+
+use super::Opcode;
+
+// Receives an event for every instruction an `Intcode` program executes
+pub trait Tracer {
+    // Lets the VM skip building events entirely when nobody is listening
+    const ENABLED: bool = true;
+
+    fn trace(&mut self, event: &TraceEvent);
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TraceEvent {
+    pub ip: usize,
+    // The relative base before the instruction executed
+    pub relative_base: i64,
+    pub opcode: Opcode,
+    // The values of every parameter the instruction read, in order. The target of a
+    // jump that isn't taken is never read, so it isn't included.
+    pub operands: Vec<ResolvedOperand>,
+    // Every word the instruction wrote, in order. Built-in instructions write at most
+    // one, but added opcodes can write more.
+    pub writes: Vec<MemoryWrite>,
+    // Values the instruction read and output, in order. Built-in instructions read or
+    // output at most one, but added opcodes can do more.
+    pub inputs: Vec<i64>,
+    pub outputs: Vec<i64>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ResolvedOperand {
+    // The address the value was loaded from, or `None` for immediate parameters
+    pub address: Option<usize>,
+    pub value: i64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub old_value: i64,
+    pub value: i64,
+}
+
+// Writes one line per executed instruction, e.g. `0004 rb=0 ADD 3, 5 -> [100]=8`
+pub struct TraceWriter<W: Write> {
+    writer: W,
+    error: Option<io::Error>,
+}
+
+impl Tracer for () {
+    const ENABLED: bool = false;
+
+    fn trace(&mut self, _: &TraceEvent) {}
+}
+
+impl Tracer for Vec<TraceEvent> {
+    fn trace(&mut self, event: &TraceEvent) {
+        self.push(event.clone());
+    }
+}
+
+impl<T> Tracer for T
+where
+    T: FnMut(&TraceEvent),
+{
+    fn trace(&mut self, event: &TraceEvent) {
+        self(event);
+    }
+}
+
+impl<W: Write> TraceWriter<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            error: None,
+        }
+    }
+
+    // Returns the underlying writer, or the first error encountered while tracing
+    pub fn into_inner(self) -> io::Result<W> {
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(self.writer),
+        }
+    }
+}
+
+impl<W: Write> Tracer for TraceWriter<W> {
+    fn trace(&mut self, event: &TraceEvent) {
+        if self.error.is_none() {
+            self.error = writeln!(self.writer, "{}", event).err();
+        }
+    }
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04} rb={} {}",
+            self.ip,
+            self.relative_base,
+            self.opcode.mnemonic()
+        )?;
+        let operands = self
+            .operands
+            .iter()
+            .map(|operand| operand.value.to_string())
+            .collect::<Vec<_>>();
+        if !operands.is_empty() {
+            write!(f, " {}", operands.join(", "))?;
+        }
+        for write in &self.writes {
+            write!(f, " -> [{}]={}", write.address, write.value)?;
+        }
+        for input in &self.inputs {
+            write!(f, " in={}", input)?;
+        }
+        for output in &self.outputs {
+            write!(f, " out={}", output)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, Intcode, IoBus};
+
+    fn program() -> Intcode {
+        let source = "
+                    IN -> [value]
+                    MUL [value], #2 -> [value]
+                    JZ [value], #0
+                    OUT [value]
+                    HALT
+            value:  .data 0
+        ";
+        Intcode::new(assemble(source).unwrap())
+    }
+
+    #[test]
+    pub fn test_collect_events() {
+        let mut events = Vec::new();
+        program()
+            .execute_traced(|| 21, |_| {}, &mut events)
+            .unwrap();
+        assert_eq!(5, events.len());
+        assert_eq!(
+            TraceEvent {
+                ip: 2,
+                relative_base: 0,
+                opcode: Opcode::Multiply,
+                operands: vec![
+                    ResolvedOperand {
+                        address: Some(12),
+                        value: 21
+                    },
+                    ResolvedOperand {
+                        address: None,
+                        value: 2
+                    },
+                ],
+                writes: vec![MemoryWrite {
+                    address: 12,
+                    old_value: 21,
+                    value: 42
+                }],
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+            },
+            events[1]
+        );
+        assert_eq!(1, events[2].operands.len());
+        assert_eq!(vec![42], events[3].outputs);
+    }
+
+    #[test]
+    pub fn test_tracing_keeps_behaviour() {
+        // A jump that isn't taken never reads its target, and an input that isn't
+        // available never resolves its destination
+        for code in &[
+            "105,0,-1,99",
+            "1106,0,-1,99",
+            "203,-1,99",
+            "1,0,0,0,4,-1,99",
+        ] {
+            let program = code.parse::<Intcode>().unwrap();
+            let mut untraced = program.clone();
+            let mut traced = program;
+            let mut events = Vec::new();
+            assert_eq!(
+                untraced.execute_with_io(IoBus::default(), |_| {}),
+                traced.execute_traced(IoBus::default(), |_| {}, &mut events),
+                "{}",
+                code
+            );
+            assert_eq!(untraced.ip(), traced.ip());
+        }
+    }
+
+    #[test]
+    pub fn test_trace_writer() {
+        let mut tracer = TraceWriter::new(Vec::new());
+        program()
+            .execute_traced(|| 21, |_| {}, &mut tracer)
+            .unwrap();
+        let log = String::from_utf8(tracer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            "0000 rb=0 IN -> [12]=21 in=21
+0002 rb=0 MUL 21, 2 -> [12]=42
+0006 rb=0 JZ 42
+0009 rb=0 OUT 42 out=42
+0011 rb=0 HALT
+",
+            log
+        );
+    }
+}
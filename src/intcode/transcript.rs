@@ -0,0 +1,401 @@
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufRead, ErrorKind, Write};
+
+use super::{Budget, Input, Intcode, RunOutcome, TraceEvent, Tracer, VmError};
+
+// Every value a program read and wrote, in order. Pass a transcript as the tracer to
+// `execute_traced` (or record a `Driver` or `Console`) to fill it in, then save it and
+// replay it later to check that a program still behaves the same way.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Transcript {
+    events: Vec<IoEvent>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum IoEvent {
+    Input(i64),
+    Output(i64),
+}
+
+// Where a replayed program stopped matching its transcript. Positions are indexes into
+// the transcript's events.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Divergence {
+    // The program output `actual` where the transcript has `expected`, which is `None`
+    // past the end of the transcript
+    Output {
+        position: usize,
+        expected: Option<IoEvent>,
+        actual: i64,
+    },
+    // The program at `ip` asked for input where the transcript has an output
+    Input {
+        position: usize,
+        ip: usize,
+        expected: IoEvent,
+    },
+    // The program halted before the end of the transcript
+    Halted {
+        position: usize,
+        expected: IoEvent,
+    },
+    BudgetExhausted {
+        position: usize,
+    },
+    Error(VmError),
+}
+
+// Feeds a program the inputs from a transcript for as long as the transcript expects input
+struct Replay<'a> {
+    events: &'a [IoEvent],
+    position: usize,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn events(&self) -> &[IoEvent] {
+        &self.events
+    }
+
+    pub fn push(&mut self, event: IoEvent) {
+        self.events.push(event);
+    }
+
+    pub fn inputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|event| match *event {
+            IoEvent::Input(value) => Some(value),
+            IoEvent::Output(_) => None,
+        })
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = i64> + '_ {
+        self.events.iter().filter_map(|event| match *event {
+            IoEvent::Output(value) => Some(value),
+            IoEvent::Input(_) => None,
+        })
+    }
+
+    // Writes one event per line, e.g. `in 5` or `out -1`
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        for event in &self.events {
+            writeln!(writer, "{}", event)?;
+        }
+        writer.flush()
+    }
+
+    // Reads a transcript written by `save`, skipping blank lines and `#` comments
+    pub fn load<R: BufRead>(reader: R) -> io::Result<Transcript> {
+        let mut transcript = Transcript::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event = parse_event(line).ok_or_else(|| {
+                io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid transcript event on line {}: {}", number + 1, line),
+                )
+            })?;
+            transcript.push(event);
+        }
+        Ok(transcript)
+    }
+
+    // Runs `program` with the recorded inputs, checking that it produces exactly the
+    // recorded outputs in the same order. Returns why the program stopped once the
+    // whole transcript has been replayed.
+    pub fn replay(&self, program: &mut Intcode) -> Result<RunOutcome, Divergence> {
+        self.replay_with_budget(program, &mut Budget::unlimited())
+    }
+
+    // Like `replay`, but gives up once the budget is exhausted, in case the program
+    // diverges into a loop that never reads or writes anything
+    pub fn replay_with_budget(
+        &self,
+        program: &mut Intcode,
+        budget: &mut Budget,
+    ) -> Result<RunOutcome, Divergence> {
+        let mut replay = Replay {
+            events: &self.events,
+            position: 0,
+        };
+        loop {
+            let outcome = program.run_with_budget(&mut replay, budget)?;
+            let position = replay.position;
+            let expected = self.events.get(position).copied();
+            match outcome {
+                RunOutcome::Output(actual) => {
+                    if expected != Some(IoEvent::Output(actual)) {
+                        return Err(Divergence::Output {
+                            position,
+                            expected,
+                            actual,
+                        });
+                    }
+                    replay.position += 1;
+                }
+                outcome @ RunOutcome::NeedsInput | outcome @ RunOutcome::Halted => {
+                    return match (outcome, expected) {
+                        (_, None) => Ok(outcome),
+                        (RunOutcome::NeedsInput, Some(expected)) => Err(Divergence::Input {
+                            position,
+                            ip: program.ip(),
+                            expected,
+                        }),
+                        (_, Some(expected)) => Err(Divergence::Halted { position, expected }),
+                    };
+                }
+                RunOutcome::BudgetExhausted => {
+                    return Err(Divergence::BudgetExhausted { position });
+                }
+            }
+        }
+    }
+}
+
+fn parse_event(line: &str) -> Option<IoEvent> {
+    let mut words = line.split_whitespace();
+    let kind = words.next()?;
+    let value = words.next()?.parse().ok()?;
+    if words.next().is_some() {
+        return None;
+    }
+    match kind {
+        "in" => Some(IoEvent::Input(value)),
+        "out" => Some(IoEvent::Output(value)),
+        _ => None,
+    }
+}
+
+impl Tracer for Transcript {
+    fn trace(&mut self, event: &TraceEvent) {
+        for &value in &event.inputs {
+            self.push(IoEvent::Input(value));
+        }
+        for &value in &event.outputs {
+            self.push(IoEvent::Output(value));
+        }
+    }
+}
+
+impl Input for &mut Replay<'_> {
+    fn get_input(&mut self) -> Option<i64> {
+        match self.events.get(self.position) {
+            Some(&IoEvent::Input(value)) => {
+                self.position += 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Display for IoEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            IoEvent::Input(value) => write!(f, "in {}", value),
+            IoEvent::Output(value) => write!(f, "out {}", value),
+        }
+    }
+}
+
+impl From<VmError> for Divergence {
+    fn from(e: VmError) -> Self {
+        Divergence::Error(e)
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Divergence::Output {
+                position,
+                expected: Some(expected),
+                actual,
+            } => write!(
+                f,
+                "event {}: expected `{}` but the program output {}",
+                position, expected, actual
+            ),
+            Divergence::Output {
+                position, actual, ..
+            } => write!(
+                f,
+                "event {}: the program output {} after the end of the transcript",
+                position, actual
+            ),
+            Divergence::Input {
+                position,
+                ip,
+                expected,
+            } => write!(
+                f,
+                "event {}: expected `{}` but the program asked for input at ip {}",
+                position, expected, ip
+            ),
+            Divergence::Halted { position, expected } => write!(
+                f,
+                "event {}: expected `{}` but the program halted",
+                position, expected
+            ),
+            Divergence::BudgetExhausted { position } => {
+                write!(f, "event {}: the program ran out of budget", position)
+            }
+            Divergence::Error(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for Divergence {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::{assemble, IoBus};
+
+    // Outputs the running total of its inputs, until it reads a zero
+    fn totals(step: i64) -> Intcode {
+        let source = format!(
+            "
+            loop:   IN -> [value]
+                    JZ [value], #end
+                    MUL [value], #{} -> [value]
+                    ADD [total], [value] -> [total]
+                    OUT [total]
+                    JZ #0, #loop
+            end:    HALT
+            value:  .data 0
+            total:  .data 0
+        ",
+            step
+        );
+        Intcode::new(assemble(&source).unwrap())
+    }
+
+    fn record(inputs: &[i64]) -> Transcript {
+        let mut input = IoBus::default();
+        for &value in inputs {
+            input.write(value);
+        }
+        let mut transcript = Transcript::new();
+        totals(1)
+            .execute_traced(input, |_| {}, &mut transcript)
+            .unwrap();
+        transcript
+    }
+
+    #[test]
+    pub fn test_record_and_replay() {
+        let transcript = record(&[1, 2, 0]);
+        assert_eq!(
+            &[
+                IoEvent::Input(1),
+                IoEvent::Output(1),
+                IoEvent::Input(2),
+                IoEvent::Output(3),
+                IoEvent::Input(0),
+            ],
+            transcript.events()
+        );
+        assert_eq!(vec![1, 2, 0], transcript.inputs().collect::<Vec<_>>());
+        assert_eq!(vec![1, 3], transcript.outputs().collect::<Vec<_>>());
+        assert_eq!(Ok(RunOutcome::Halted), transcript.replay(&mut totals(1)));
+
+        let transcript = record(&[1, 2]);
+        assert_eq!(
+            Ok(RunOutcome::NeedsInput),
+            transcript.replay(&mut totals(1))
+        );
+    }
+
+    #[test]
+    pub fn test_save_and_load() {
+        let transcript = record(&[5, -7, 0]);
+        let mut file = Vec::new();
+        transcript.save(&mut file).unwrap();
+        assert_eq!(
+            "in 5\nout 5\nin -7\nout -2\nin 0\n",
+            String::from_utf8(file.clone()).unwrap()
+        );
+        assert_eq!(transcript, Transcript::load(file.as_slice()).unwrap());
+
+        let commented = "# a comment\n\nin 5\n  out 5  \n";
+        assert_eq!(
+            &[IoEvent::Input(5), IoEvent::Output(5)],
+            Transcript::load(commented.as_bytes()).unwrap().events()
+        );
+        for invalid in &["in\n", "in x\n", "out 1 2\n", "read 1\n"] {
+            let error = Transcript::load(invalid.as_bytes()).unwrap_err();
+            assert_eq!(ErrorKind::InvalidData, error.kind());
+        }
+    }
+
+    #[test]
+    pub fn test_divergence() {
+        let transcript = record(&[1, 2, 0]);
+        assert_eq!(
+            Err(Divergence::Output {
+                position: 1,
+                expected: Some(IoEvent::Output(1)),
+                actual: 2
+            }),
+            transcript.replay(&mut totals(2))
+        );
+
+        // A program that reads everything and halts without output
+        let source = "
+            loop:   IN -> [value]
+                    JNZ [value], #loop
+                    HALT
+            value:  .data 0
+        ";
+        let mut silent = Intcode::new(assemble(source).unwrap());
+        assert_eq!(
+            Err(Divergence::Input {
+                position: 1,
+                ip: 0,
+                expected: IoEvent::Output(1)
+            }),
+            transcript.replay(&mut silent)
+        );
+
+        let mut short = "3,100,99".parse().unwrap();
+        assert_eq!(
+            Err(Divergence::Halted {
+                position: 1,
+                expected: IoEvent::Output(1)
+            }),
+            transcript.replay(&mut short)
+        );
+
+        let mut chatty = "104,1,104,2,99".parse().unwrap();
+        assert_eq!(
+            Err(Divergence::Output {
+                position: 0,
+                expected: Some(IoEvent::Input(1)),
+                actual: 1
+            }),
+            transcript.replay(&mut chatty)
+        );
+        assert_eq!(
+            Err(Divergence::Output {
+                position: 0,
+                expected: None,
+                actual: 1
+            }),
+            Transcript::new().replay(&mut "104,1,99".parse().unwrap())
+        );
+
+        let mut spinner = "1105,1,0".parse().unwrap();
+        assert_eq!(
+            Err(Divergence::BudgetExhausted { position: 0 }),
+            transcript.replay_with_budget(&mut spinner, &mut Budget::steps(100))
+        );
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use super::control_flow::instruction_at;
+use super::{
+    control_flow_graph, disassemble, InstructionSet, Op, Opcode, Parameter, ParameterMode,
+};
+
+// Words per line in the generated image
+const IMAGE_LINE_WORDS: usize = 12;
+
+// Translates a memory image into the source of a Rust module with a `Program` type that
+// runs it like an `Intcode` would. Every instruction found by disassembling the image or
+// by following its jumps is compiled ahead of time. Each compiled instruction checks that
+// its opcode and parameter modes haven't been overwritten before it runs, and anything
+// else (including self-modified code) falls back to a small interpreter, so the
+// generated code behaves the same as the interpreter for any program. `intcode_path` is
+// the path the generated code imports the `Input` and `Output` traits from, such as
+// `aoc2019::intcode`. Only the built-in opcodes are compiled.
+pub fn transpile(memory: &[i64], intcode_path: &str) -> String {
+    let instructions = InstructionSet::default();
+    let mut addresses = disassemble(memory)
+        .entries()
+        .iter()
+        .filter(|entry| !entry.is_data())
+        .map(|entry| entry.address())
+        .collect::<BTreeSet<_>>();
+    for block in control_flow_graph(memory).blocks() {
+        addresses.extend(&block.instructions);
+    }
+
+    let mut source = String::new();
+    writeln!(
+        source,
+        "// Generated by `intcode-transpile` from a {}-word Intcode image. Do not edit.",
+        memory.len()
+    )
+    .unwrap();
+    source.push_str(&HEADER.replace("{intcode_path}", intcode_path));
+
+    writeln!(source, "\n#[rustfmt::skip]").unwrap();
+    writeln!(source, "const IMAGE: [i64; {}] = [", memory.len()).unwrap();
+    for line in memory.chunks(IMAGE_LINE_WORDS) {
+        let words = line.iter().map(i64::to_string).collect::<Vec<_>>();
+        writeln!(source, "    {},", words.join(", ")).unwrap();
+    }
+    writeln!(source, "];").unwrap();
+
+    source.push_str(PROGRAM);
+    for address in addresses {
+        if let Some(op) = instruction_at(memory, address, &instructions) {
+            compile(&mut source, memory[address], address, &op);
+        }
+    }
+    source.push_str(RUNTIME);
+    source
+}
+
+// Writes the match arm for one instruction
+fn compile(source: &mut String, header: i64, address: usize, op: &Op) {
+    let word = |i: usize| address + 1 + i;
+    let value = |i: usize| operand(op.parameters[i], word(i));
+    let destination = |i: usize| match op.parameters[i].mode {
+        ParameterMode::Position => format!("self.position(self.read({}))?", word(i)),
+        ParameterMode::Relative => format!("self.relative(self.read({}))?", word(i)),
+        ParameterMode::Immediate => unreachable!("instructions never write to immediates"),
+    };
+    let next = address + 1 + op.parameters().len();
+
+    let mut body = Vec::new();
+    match op.opcode {
+        Opcode::Add | Opcode::Multiply | Opcode::LessThan | Opcode::Equals => {
+            body.push(format!("let a = {};", value(0)));
+            body.push(format!("let b = {};", value(1)));
+            body.push(format!("let address = {};", destination(2)));
+            let result = match op.opcode {
+                Opcode::Add => "a + b",
+                Opcode::Multiply => "a * b",
+                Opcode::LessThan => "i64::from(a < b)",
+                _ => "i64::from(a == b)",
+            };
+            body.push(format!("self.store(address, {});", result));
+        }
+        Opcode::Input => {
+            body.push("let value = match input.get_input() {".to_string());
+            body.push("    Some(value) => value,".to_string());
+            body.push("    None => return Ok(RunOutcome::NeedsInput),".to_string());
+            body.push("};".to_string());
+            body.push(format!("let address = {};", destination(0)));
+            body.push("self.store(address, value);".to_string());
+        }
+        Opcode::Output => {
+            body.push(format!("let value = {};", value(0)));
+            body.push("output.receive_output(value);".to_string());
+            body.push(format!("self.ip = {};", next));
+            body.push("if stop_on_output {".to_string());
+            body.push("    return Ok(RunOutcome::Output(value));".to_string());
+            body.push("}".to_string());
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+            let comparison = if op.opcode == Opcode::JumpIfTrue {
+                "!="
+            } else {
+                "=="
+            };
+            body.push(format!("if {} {} 0 {{", value(0), comparison));
+            body.push(format!("    self.ip = self.jump({})?;", value(1)));
+            body.push("} else {".to_string());
+            body.push(format!("    self.ip = {};", next));
+            body.push("}".to_string());
+        }
+        Opcode::RelativeBaseOffset => {
+            body.push(format!("self.relative_base += {};", value(0)));
+        }
+        Opcode::Halt => {
+            body.push("self.halted = true;".to_string());
+            body.push("return Ok(RunOutcome::Halted);".to_string());
+        }
+        Opcode::Extended(_) => unreachable!("only built-in opcodes are transpiled"),
+    }
+    let jumps = op.opcode == Opcode::JumpIfTrue || op.opcode == Opcode::JumpIfFalse;
+    if !jumps && op.opcode != Opcode::Output && op.opcode != Opcode::Halt {
+        body.push(format!("self.ip = {};", next));
+    }
+
+    writeln!(
+        source,
+        "                {} if self.read({}) == {} => {{",
+        address, address, header
+    )
+    .unwrap();
+    for line in body {
+        writeln!(source, "                    {}", line).unwrap();
+    }
+    writeln!(source, "                }}").unwrap();
+}
+
+// An expression for the value of a parameter whose value is stored at `word`
+fn operand(parameter: Parameter, word: usize) -> String {
+    match parameter.mode {
+        ParameterMode::Position => format!("self.read(self.position(self.read({}))?)", word),
+        ParameterMode::Immediate => format!("self.read({})", word),
+        ParameterMode::Relative => format!("self.read(self.relative(self.read({}))?)", word),
+    }
+}
+
+const HEADER: &str = "//
+// Instructions found in the image are compiled into the `match` in `resume`. Each one
+// checks that its opcode and parameter modes haven't been overwritten before it runs,
+// and anything else is left to `step`, which interprets one instruction at a time.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+
+use {intcode_path}::{Input, Output, RunOutcome, VmError};
+
+// Writes from this address on are kept in a map rather than growing memory
+const FAR_MEMORY: usize = 1 << 24;
+";
+
+const PROGRAM: &str = "
+#[derive(Clone, Debug)]
+pub struct Program {
+    memory: Vec<i64>,
+    far: HashMap<usize, i64>,
+    ip: usize,
+    relative_base: i64,
+    halted: bool,
+}
+
+impl Default for Program {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Program {
+    pub fn new() -> Self {
+        Self::with_memory(IMAGE.to_vec())
+    }
+
+    // Runs the compiled code on a different image, such as a patched copy of the original
+    pub fn with_memory(memory: Vec<i64>) -> Self {
+        Self {
+            memory,
+            far: HashMap::new(),
+            ip: 0,
+            relative_base: 0,
+            halted: false,
+        }
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> i64 {
+        self.relative_base
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Memory up to the highest address written below `FAR_MEMORY`
+    pub fn memory(&self) -> &[i64] {
+        &self.memory
+    }
+
+    pub fn read(&self, address: usize) -> i64 {
+        match self.memory.get(address) {
+            Some(&value) => value,
+            None => self.far.get(&address).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn execute(&mut self) -> Result<RunOutcome, VmError> {
+        self.execute_with_io(|| 0, |_| {})
+    }
+
+    // Runs until the program halts or needs input that isn't available
+    pub fn execute_with_io<I: Input, O: Output>(
+        &mut self,
+        mut input: I,
+        mut output: O,
+    ) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut output, false)
+    }
+
+    // Runs until the program produces an output, halts or needs input that isn't available
+    pub fn run<I: Input>(&mut self, mut input: I) -> Result<RunOutcome, VmError> {
+        self.resume(&mut input, &mut |_| {}, true)
+    }
+
+    fn resume<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+        stop_on_output: bool,
+    ) -> Result<RunOutcome, VmError> {
+        if self.halted {
+            return Ok(RunOutcome::Halted);
+        }
+        loop {
+            match self.ip {
+";
+
+const RUNTIME: &str = "                _ => match self.step(input, output)? {
+                    Some(RunOutcome::Output(_)) if !stop_on_output => {}
+                    Some(outcome) => return Ok(outcome),
+                    None => {}
+                },
+            }
+        }
+    }
+
+    // Interprets the instruction at `ip`, returning why the program stopped or the
+    // value it output, if any
+    fn step<I: Input, O: Output>(
+        &mut self,
+        input: &mut I,
+        output: &mut O,
+    ) -> Result<Option<RunOutcome>, VmError> {
+        let ip = self.ip;
+        let header = self.read(ip);
+        let opcode = header % 100;
+        let parameters = match opcode {
+            1 | 2 | 7 | 8 => 3,
+            5 | 6 => 2,
+            3 | 4 | 9 => 1,
+            99 => 0,
+            _ => return Err(VmError::UnknownOpcode { opcode: header, ip }),
+        };
+        let mut modes = [0; 3];
+        let mut digits = header / 100;
+        for mode in modes.iter_mut().take(parameters) {
+            *mode = digits % 10;
+            if !(0..=2).contains(mode) {
+                return Err(VmError::InvalidParameterMode { mode: *mode, ip });
+            }
+            digits /= 10;
+        }
+
+        match opcode {
+            1 | 2 | 7 | 8 => {
+                let a = self.operand(modes[0], ip + 1)?;
+                let b = self.operand(modes[1], ip + 2)?;
+                let address = self.address(modes[2], ip + 3)?;
+                let value = match opcode {
+                    1 => a + b,
+                    2 => a * b,
+                    7 => i64::from(a < b),
+                    _ => i64::from(a == b),
+                };
+                self.store(address, value);
+            }
+            3 => {
+                let value = match input.get_input() {
+                    Some(value) => value,
+                    None => return Ok(Some(RunOutcome::NeedsInput)),
+                };
+                let address = self.address(modes[0], ip + 1)?;
+                self.store(address, value);
+            }
+            4 => {
+                let value = self.operand(modes[0], ip + 1)?;
+                output.receive_output(value);
+                self.ip = ip + 2;
+                return Ok(Some(RunOutcome::Output(value)));
+            }
+            5 | 6 => {
+                if (self.operand(modes[0], ip + 1)? != 0) == (opcode == 5) {
+                    self.ip = self.jump(self.operand(modes[1], ip + 2)?)?;
+                    return Ok(None);
+                }
+            }
+            9 => self.relative_base += self.operand(modes[0], ip + 1)?,
+            _ => {
+                self.halted = true;
+                return Ok(Some(RunOutcome::Halted));
+            }
+        }
+        self.ip = ip + 1 + parameters;
+        Ok(None)
+    }
+
+    fn operand(&self, mode: i64, word: usize) -> Result<i64, VmError> {
+        let value = self.read(word);
+        match mode {
+            0 => Ok(self.read(self.position(value)?)),
+            1 => Ok(value),
+            _ => Ok(self.read(self.relative(value)?)),
+        }
+    }
+
+    fn address(&self, mode: i64, word: usize) -> Result<usize, VmError> {
+        match mode {
+            0 => self.position(self.read(word)),
+            1 => Err(VmError::WriteToImmediate { ip: self.ip }),
+            _ => self.relative(self.read(word)),
+        }
+    }
+
+    fn position(&self, address: i64) -> Result<usize, VmError> {
+        if address < 0 {
+            return Err(VmError::NegativeAddress {
+                address,
+                ip: self.ip,
+            });
+        }
+        Ok(address as usize)
+    }
+
+    fn relative(&self, offset: i64) -> Result<usize, VmError> {
+        self.position(self.relative_base + offset)
+    }
+
+    fn jump(&self, target: i64) -> Result<usize, VmError> {
+        if target < 0 {
+            return Err(VmError::NegativeJump {
+                target,
+                ip: self.ip,
+            });
+        }
+        Ok(target as usize)
+    }
+
+    fn store(&mut self, address: usize, value: i64) {
+        if address < self.memory.len() {
+            self.memory[address] = value;
+        } else if address < FAR_MEMORY {
+            self.memory.resize(address + 1, 0);
+            self.memory[address] = value;
+        } else {
+            self.far.insert(address, value);
+        }
+    }
+}
+";
+
+// The generated code for the test programs is checked in under `testdata`, and for a
+// fuzzing case under `fuzz`, so that it's compiled and tested along with everything
+// else. Regenerate it with `UPDATE_TRANSPILED=1 cargo test transpiler`.
+#[cfg(test)]
+#[path = "testdata/self_modifying.rs"]
+mod self_modifying;
+#[cfg(test)]
+#[path = "testdata/squares.rs"]
+mod squares;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::intcode::fuzz::TRANSPILED_SEED;
+    use crate::intcode::{assemble, FuzzCase, Intcode, IoBus, RunOutcome, VmError};
+    use std::{env, fs};
+
+    // The program from the interpreter's test of self-modifying code
+    const SELF_MODIFYING: &str = "
+        start:  OUT #1
+                ADD [start+1], #1 -> [start+1]
+                EQ [start+1], #3 -> [flag]
+                JZ [flag], #start
+                ADD #99, #0 -> [start]
+                JZ #0, #start
+        flag:   .data 0
+    ";
+
+    // Outputs the sum of the squares up to each input, and whether it's more than 100,
+    // using every opcode and parameter mode
+    const SQUARES: &str = "
+        next:   IN -> [n]
+                JZ [n], #done
+                ARB #squares
+                ADD #0, #0 -> [total]
+        loop:   MUL [n], [n] -> [rb+0]
+                ADD [total], [rb+0] -> [total]
+                ARB #1
+                ADD [n], #-1 -> [n]
+                LT #0, [n] -> [flag]
+                JNZ [flag], #loop
+                OUT [total]
+                LT #100, [total] -> [flag]
+                EQ [flag], #1 -> [flag]
+                OUT [flag]
+                JNZ #1, #next
+        done:   HALT
+        n:       .data 0
+        total:   .data 0
+        flag:    .data 0
+        squares: .data 0
+    ";
+
+    fn bus(values: &[i64]) -> IoBus {
+        let mut bus = IoBus::default();
+        for &value in values {
+            bus.write(value);
+        }
+        bus
+    }
+
+    #[test]
+    pub fn test_generated_code_is_up_to_date() {
+        let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/src/intcode");
+        let images = [
+            ("testdata/self_modifying", assemble(SELF_MODIFYING).unwrap()),
+            ("testdata/squares", assemble(SQUARES).unwrap()),
+            (
+                "fuzz/transpiled",
+                FuzzCase::generate(TRANSPILED_SEED).memory,
+            ),
+        ];
+        for (name, memory) in &images {
+            let generated = transpile(memory, "crate::intcode");
+            let path = format!("{}/{}.rs", directory, name);
+            if env::var_os("UPDATE_TRANSPILED").is_some() {
+                fs::write(&path, &generated).unwrap();
+            }
+            assert_eq!(
+                fs::read_to_string(&path).unwrap(),
+                generated,
+                "{} is out of date",
+                path
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_self_modifying_code() {
+        let mut program = self_modifying::Program::new();
+        let mut output = Vec::new();
+        program
+            .execute_with_io(
+                || 0,
+                |v| {
+                    assert!(output.len() < 2, "stale instruction executed");
+                    output.push(v);
+                },
+            )
+            .unwrap();
+        assert_eq!(vec![1, 2], output);
+        assert!(program.is_halted());
+    }
+
+    #[test]
+    pub fn test_matches_interpreter() {
+        let inputs = [3, 10, 1, 0];
+        let mut interpreter = Intcode::new(assemble(SQUARES).unwrap());
+        let mut expected = Vec::new();
+        interpreter
+            .execute_with_io(bus(&inputs), |v| expected.push(v))
+            .unwrap();
+        assert_eq!(vec![14, 0, 385, 1, 1, 0], expected);
+
+        let mut program = squares::Program::new();
+        let mut output = Vec::new();
+        assert_eq!(
+            Ok(RunOutcome::Halted),
+            program.execute_with_io(bus(&inputs), |v| output.push(v))
+        );
+        assert_eq!(expected, output);
+        assert_eq!(interpreter.ip(), program.ip());
+        assert_eq!(interpreter.relative_base(), program.relative_base());
+    }
+
+    #[test]
+    pub fn test_run_outcomes() {
+        let mut program = squares::Program::new();
+        let mut input = IoBus::default();
+        assert_eq!(Ok(RunOutcome::NeedsInput), program.run(&mut input));
+        input.write(2);
+        assert_eq!(Ok(RunOutcome::Output(5)), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::Output(0)), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::NeedsInput), program.run(&mut input));
+        input.write(0);
+        assert_eq!(Ok(RunOutcome::Halted), program.run(&mut input));
+        assert_eq!(Ok(RunOutcome::Halted), program.execute());
+    }
+
+    #[test]
+    pub fn test_other_images() {
+        // The interpreter fallback runs anything the compiled code doesn't cover
+        let run = |code: &str| {
+            let memory = code.split(',').map(|word| word.parse().unwrap()).collect();
+            squares::Program::with_memory(memory).execute()
+        };
+        assert_eq!(
+            Err(VmError::UnknownOpcode { opcode: 42, ip: 4 }),
+            run("1101,1,1,0,42")
+        );
+        assert_eq!(
+            Err(VmError::InvalidParameterMode { mode: 3, ip: 0 }),
+            run("301,0,0,0,99")
+        );
+        assert_eq!(
+            Err(VmError::WriteToImmediate { ip: 0 }),
+            run("11101,1,1,0,99")
+        );
+        assert_eq!(
+            Err(VmError::NegativeAddress { address: -1, ip: 0 }),
+            run("1,-1,0,0,99")
+        );
+        assert_eq!(
+            Err(VmError::NegativeJump { target: -5, ip: 0 }),
+            run("1105,1,-5,99")
+        );
+
+        let mut program =
+            squares::Program::with_memory(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        assert_eq!(Ok(RunOutcome::Halted), program.execute());
+        assert_eq!(
+            &[3500, 9, 10, 70, 2, 3, 11, 0, 99, 30, 40, 50],
+            program.memory()
+        );
+
+        // Stores 42 at 10^12 and reads it back
+        let memory = vec![21101, 0, 42, 1_000_000_000_000, 4, 1_000_000_000_000, 99];
+        let mut program = squares::Program::with_memory(memory);
+        let mut output = Vec::new();
+        program.execute_with_io(|| 0, |v| output.push(v)).unwrap();
+        assert_eq!(vec![42], output);
+        assert_eq!(7, program.memory().len());
+    }
+}
@@ -4,7 +4,7 @@ extern crate aoc_runner_derive;
 
 #[macro_use]
 mod geometry;
-mod intcode;
+pub mod intcode;
 
 mod day01;
 mod day02;
@@ -28,5 +28,7 @@ mod day19;
 mod day20;
 mod day21;
 mod day22;
+mod day23;
+mod day25;
 
 aoc_lib! { year = 2019 }